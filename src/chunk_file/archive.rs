@@ -0,0 +1,213 @@
+// zip bundles are expanded into a directory named after the archive, every supported member is
+// a document of its own, named like "archive.zip/path/inside"
+
+use super::parser::{sniff, Content};
+use super::{match_file, UnlearnedFile};
+use crate::{ARCHIVE_MAX_BYTES, ARCHIVE_MAX_ENTRIES};
+use anyhow::Result;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Component, Path};
+use zip::ZipArchive;
+
+pub struct Member {
+    pub name: String,
+    // unsupported members are not kept
+    pub file: Result<UnlearnedFile>,
+}
+
+// a plain zip, zip based documents like docx or epub are told apart by sniff
+pub fn is_archive(path: &Path) -> bool {
+    matches!(sniff(path), Ok(Content::Typed(mime_type)) if mime_type == "application/zip")
+}
+
+// a broken or oversized archive fails as a whole, the caller removes dir then
+pub fn expand(archive_name: &str, path: &Path, dir: &Path, uploader: &str) -> Result<Vec<Member>> {
+    expand_limited(
+        archive_name,
+        path,
+        dir,
+        uploader,
+        *ARCHIVE_MAX_ENTRIES,
+        *ARCHIVE_MAX_BYTES,
+    )
+}
+
+fn expand_limited(
+    archive_name: &str,
+    path: &Path,
+    dir: &Path,
+    uploader: &str,
+    max_entries: usize,
+    max_bytes: u64,
+) -> Result<Vec<Member>> {
+    let mut archive = ZipArchive::new(File::open(path)?)?;
+    if archive.len() > max_entries {
+        return Err(anyhow::anyhow!(
+            "too many entries: {} > {}",
+            archive.len(),
+            max_entries
+        ));
+    }
+    // sizes in the headers may lie, the written bytes are counted again below
+    let declared = (0..archive.len())
+        .filter_map(|i| archive.by_index_raw(i).ok().map(|e| e.size()))
+        .sum::<u64>();
+    if declared > max_bytes {
+        return Err(anyhow::anyhow!(
+            "too large: {} > {} bytes",
+            declared,
+            max_bytes
+        ));
+    }
+
+    let mut members = vec![];
+    // names differing only in separators, like "a//b" and "a/b", are the same file
+    let mut names = HashSet::new();
+    let mut written = 0;
+    for i in 0..archive.len() {
+        let name = archive.name_for_index(i).unwrap_or_default().to_string();
+        let mut entry = match archive.by_index(i) {
+            Ok(entry) => entry,
+            Err(e) => {
+                members.push(Member {
+                    name: format!("{}/{}", archive_name, name),
+                    file: Err(e.into()),
+                });
+                continue;
+            }
+        };
+        if entry.is_dir() {
+            continue;
+        }
+        let Some(relative) = entry
+            .enclosed_name()
+            .filter(|p| p.components().all(|c| matches!(c, Component::Normal(_))))
+        else {
+            members.push(Member {
+                name: format!("{}/{}", archive_name, entry.name()),
+                file: Err(anyhow::anyhow!("unsafe path")),
+            });
+            continue;
+        };
+        let parts = relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().to_string())
+            .collect::<Vec<_>>();
+        // metadata of macos and hidden files, not documents
+        if parts.iter().any(|p| p.starts_with('.') || p == "__MACOSX") {
+            continue;
+        }
+        let name = format!("{}/{}", archive_name, parts.join("/"));
+        if !names.insert(name.clone()) {
+            members.push(Member {
+                name,
+                file: Err(anyhow::anyhow!("duplicate name")),
+            });
+            continue;
+        }
+
+        let target = dir.join(&relative);
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut file = File::create(&target)?;
+        let limit = max_bytes - written;
+        written += std::io::copy(&mut (&mut entry).take(limit + 1), &mut file)?;
+        if written > max_bytes {
+            return Err(anyhow::anyhow!("too large: more than {} bytes", max_bytes));
+        }
+        drop(file);
+
+        let file = match_file(name.clone(), uploader.to_string(), target.clone(), None);
+        if file.is_err() {
+            let _ = std::fs::remove_file(&target);
+        }
+        members.push(Member { name, file });
+    }
+    Ok(members)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_file::test_util::zip_file;
+    use tempfile::tempdir;
+
+    // (name, error) of the members
+    fn expand_test(entries: &[(&str, &[u8])]) -> Result<Vec<(String, Option<String>)>> {
+        let archive = zip_file("a.zip", entries);
+        let dir = tempdir().unwrap();
+        let members = expand_limited("a.zip", &archive, dir.path(), "", 3, 20)?;
+        Ok(members
+            .into_iter()
+            .map(|m| (m.name, m.file.err().map(|e| e.to_string())))
+            .collect())
+    }
+
+    #[test]
+    fn expands_members() {
+        let members = expand_test(&[
+            ("docs/a.txt", b"hello"),
+            ("__MACOSX/docs/._a.txt", b""),
+            ("b.bin", &[0x00, 0x01, 0x02, 0xFF, 0xFE, 0x00, 0x80, 0x00]),
+        ])
+        .unwrap();
+        assert_eq!(members.len(), 2);
+        assert_eq!(members[0], ("a.zip/docs/a.txt".to_string(), None));
+        assert_eq!(members[1].0, "a.zip/b.bin");
+        assert!(members[1].1.is_some());
+    }
+
+    #[test]
+    fn rejects_unsafe_paths() {
+        let dir = tempdir().unwrap();
+        let archive = zip_file(
+            "a.zip",
+            &[
+                ("../evil.txt", b"x"),
+                ("/etc/evil.txt", b"x"),
+                ("a/../b.txt", b"x"),
+            ],
+        );
+        let inner = dir.path().join("a.zip");
+        let members = expand_limited("a.zip", &archive, &inner, "", 3, 20).unwrap();
+        assert_eq!(members.len(), 3);
+        assert!(members.iter().all(|m| m
+            .file
+            .as_ref()
+            .is_err_and(|e| e.to_string() == "unsafe path")));
+        assert!(!dir.path().join("evil.txt").exists());
+        assert!(!inner.exists());
+    }
+
+    #[test]
+    fn rejects_duplicate_names() {
+        let members = expand_test(&[("docs/a.txt", b"first"), ("docs//a.txt", b"second")]).unwrap();
+        assert_eq!(
+            members,
+            vec![
+                ("a.zip/docs/a.txt".to_string(), None),
+                (
+                    "a.zip/docs/a.txt".to_string(),
+                    Some("duplicate name".to_string())
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn limits_entries_and_bytes() {
+        let err = expand_test(&[
+            ("a.txt", b"a"),
+            ("b.txt", b"b"),
+            ("c.txt", b"c"),
+            ("d.txt", b"d"),
+        ])
+        .unwrap_err();
+        assert!(err.to_string().starts_with("too many entries"));
+        let err = expand_test(&[("a.txt", b"hello world"), ("b.txt", b"hello again")]).unwrap_err();
+        assert!(err.to_string().starts_with("too large"));
+    }
+}
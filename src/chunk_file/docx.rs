@@ -1,11 +1,38 @@
-use super::{chunk, insert_newlines, UnLearnedKnowledge, UnlearnedFile};
+use super::parser::DocumentParser;
+use super::{Document, Unit};
 use anyhow::Result;
 use docx_rust::document::{BodyContent, TableCellContent, TableRowContent};
 use docx_rust::DocxFile;
+use std::path::Path;
 
-pub fn parse_docx(file: UnlearnedFile) -> Result<UnLearnedKnowledge> {
+pub struct DocxParser;
+
+impl DocumentParser for DocxParser {
+    fn name(&self) -> &'static str {
+        "DOCX"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["docx"]
+    }
+
+    fn mime_types(&self) -> &'static [&'static str] {
+        &["application/vnd.openxmlformats-officedocument.wordprocessingml.document"]
+    }
+
+    fn extract(&self, path: &Path) -> Result<Document> {
+        Ok(extract_docx(path)?
+            .into_iter()
+            .map(Unit::from)
+            .collect::<Vec<_>>()
+            .into())
+    }
+}
+
+// one unit per paragraph or table row
+fn extract_docx(path: &Path) -> Result<Vec<String>> {
     let docx =
-        DocxFile::from_file(file.path).map_err(|_| anyhow::anyhow!("Failed to read docx file"))?;
+        DocxFile::from_file(path).map_err(|_| anyhow::anyhow!("Failed to read docx file"))?;
     let docx = docx
         .parse()
         .map_err(|_| anyhow::anyhow!("Failed to parse docx file"))?;
@@ -36,13 +63,5 @@ pub fn parse_docx(file: UnlearnedFile) -> Result<UnLearnedKnowledge> {
             }
         }
     }
-    let paras_with_newlines = insert_newlines(paras);
-    let unlearned_chunk_vec = chunk(paras_with_newlines.into_iter());
-
-    let unlearned_knowledge = UnLearnedKnowledge {
-        file_name: file.file_name,
-        uploader: file.uploader,
-        chunks: unlearned_chunk_vec,
-    };
-    Ok(unlearned_knowledge)
+    Ok(paras)
 }
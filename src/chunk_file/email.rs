@@ -0,0 +1,735 @@
+use super::encoding::{decode, percent_decode};
+use super::html::parse_html;
+use super::parser::{DocumentParser, PARSERS};
+use super::{Document, Unit};
+use anyhow::Result;
+use base64::engine::general_purpose::STANDARD_NO_PAD;
+use base64::Engine;
+use encoding_rs::Encoding;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+// a line of the body where the quoted message starts, everything after it is dropped
+// a line of "---- 原始邮件 ----" in any width counts too
+const QUOTE_HEADERS: [&str; 3] = [
+    "-----Original Message-----",
+    "----- Original Message -----",
+    "________________________________",
+];
+
+pub struct EmailParser;
+
+impl DocumentParser for EmailParser {
+    fn name(&self) -> &'static str {
+        "EMAIL"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["eml", "mbox"]
+    }
+
+    fn mime_types(&self) -> &'static [&'static str] {
+        &["message/rfc822", "application/mbox"]
+    }
+
+    fn text_based(&self) -> bool {
+        true
+    }
+
+    // every message: a unit of its headers, paragraphs of the body without quoted replies, then
+    // the units of its attachments, all in the section of the subject
+    // metadata: title (subject of the first message), from, to, date, and date_end of a mbox
+    // holding several days, senders of a mbox are joined by "; "
+    fn extract(&self, path: &Path) -> Result<Document> {
+        let bytes = std::fs::read(path)?;
+        let messages = split_mbox(&bytes)
+            .into_iter()
+            .map(|raw| Message::parse(&Part::parse(&raw)))
+            .collect::<Vec<_>>();
+        if messages.is_empty() {
+            return Err(anyhow::anyhow!("no message found"));
+        }
+
+        let mut units = vec![];
+        for message in &messages {
+            message.units(&mut units);
+        }
+
+        let mut metadata = BTreeMap::new();
+        let first = &messages[0];
+        if !first.subject.is_empty() {
+            metadata.insert("title".to_string(), first.subject.clone());
+        }
+        if !first.to.is_empty() {
+            metadata.insert("to".to_string(), first.to.clone());
+        }
+        let mut senders = vec![];
+        for message in &messages {
+            if !message.from.is_empty() && !senders.contains(&message.from) {
+                senders.push(message.from.clone());
+            }
+        }
+        if !senders.is_empty() {
+            metadata.insert("from".to_string(), senders.join("; "));
+        }
+        let dates = messages
+            .iter()
+            .filter_map(|m| m.date.clone())
+            .collect::<BTreeSet<_>>();
+        if let Some(date) = dates.first() {
+            metadata.insert("date".to_string(), date.clone());
+        }
+        if dates.len() > 1 {
+            metadata.insert("date_end".to_string(), dates.last().unwrap().clone());
+        }
+        Ok(Document { units, metadata })
+    }
+}
+
+// a mbox is messages after "From " lines, a single message has none
+fn split_mbox(bytes: &[u8]) -> Vec<Vec<u8>> {
+    if !bytes.starts_with(b"From ") {
+        return vec![bytes.to_vec()];
+    }
+    let mut messages = vec![];
+    let mut message: Option<Vec<u8>> = None;
+    let mut blank = true;
+    for line in bytes.split_inclusive(|b| *b == b'\n') {
+        if blank && line.starts_with(b"From ") {
+            messages.extend(message.take());
+            message = Some(vec![]);
+        } else if let Some(message) = message.as_mut() {
+            // mboxrd quoting of body lines, ">From " and ">>From " lose one '>'
+            let quoted_from = line.starts_with(b">")
+                && line
+                    .iter()
+                    .skip_while(|b| **b == b'>')
+                    .copied()
+                    .take(5)
+                    .eq(b"From ".iter().copied());
+            message.extend_from_slice(if quoted_from { &line[1..] } else { line });
+        }
+        blank = line.trim_ascii().is_empty();
+    }
+    messages.extend(message);
+    messages.retain(|m| !m.trim_ascii().is_empty());
+    messages
+}
+
+struct Part<'a> {
+    // names are lowercase, values are unfolded and decoded
+    headers: Vec<(String, String)>,
+    body: &'a [u8],
+}
+
+impl<'a> Part<'a> {
+    fn parse(bytes: &'a [u8]) -> Self {
+        // headers end at the first empty line
+        let mut end = bytes.len();
+        let mut body = bytes.len();
+        let mut offset = 0;
+        for line in bytes.split_inclusive(|b| *b == b'\n') {
+            if line.trim_ascii().is_empty() {
+                end = offset;
+                body = offset + line.len();
+                break;
+            }
+            offset += line.len();
+        }
+        let text = decode_text(&bytes[..end], None);
+        let mut headers: Vec<(String, String)> = vec![];
+        for line in text.lines() {
+            if line.starts_with([' ', '\t']) {
+                if let Some((_, value)) = headers.last_mut() {
+                    *value += " ";
+                    *value += line.trim();
+                }
+            } else if let Some((name, value)) = line.split_once(':') {
+                headers.push((name.trim().to_lowercase(), value.trim().to_string()));
+            }
+        }
+        for (_, value) in headers.iter_mut() {
+            *value = decode_words(value);
+        }
+        Part {
+            headers,
+            body: &bytes[body..],
+        }
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    // lowercase mime type and its parameters
+    fn content_type(&self) -> (String, BTreeMap<String, String>) {
+        let (mime_type, params) = parameters(self.header("content-type").unwrap_or("text/plain"));
+        (mime_type.to_lowercase(), params)
+    }
+
+    fn file_name(&self) -> Option<String> {
+        let (disposition, params) = parameters(self.header("content-disposition").unwrap_or(""));
+        params
+            .get("filename")
+            .cloned()
+            .or_else(|| self.content_type().1.get("name").cloned())
+            .filter(|name| !name.is_empty())
+            .or_else(|| (disposition.eq_ignore_ascii_case("attachment")).then(String::new))
+    }
+
+    // content transfer encoding undone
+    fn content(&self) -> Vec<u8> {
+        let encoding = self
+            .header("content-transfer-encoding")
+            .unwrap_or("")
+            .to_lowercase();
+        match encoding.as_str() {
+            "base64" => {
+                let data = self
+                    .body
+                    .iter()
+                    .filter(|b| !b.is_ascii_whitespace())
+                    .copied()
+                    .collect::<Vec<_>>();
+                let end = data.iter().rposition(|b| *b != b'=').map_or(0, |i| i + 1);
+                STANDARD_NO_PAD.decode(&data[..end]).unwrap_or_default()
+            }
+            "quoted-printable" => quoted_printable(self.body, false),
+            _ => self.body.to_vec(),
+        }
+    }
+
+    fn text(&self) -> String {
+        let charset = self.content_type().1.get("charset").cloned();
+        decode_text(&self.content(), charset.as_deref())
+    }
+
+    // bodies between the boundary lines
+    fn children(&self) -> Vec<Part<'a>> {
+        let Some(boundary) = self.content_type().1.get("boundary").cloned() else {
+            return vec![];
+        };
+        let delimiter = format!("--{}", boundary);
+        let mut parts = vec![];
+        let mut start: Option<usize> = None;
+        let mut offset = 0;
+        for line in self.body.split_inclusive(|b| *b == b'\n') {
+            let trimmed = line.trim_ascii_end();
+            if trimmed.starts_with(delimiter.as_bytes()) {
+                if let Some(start) = start {
+                    // the line break before the delimiter belongs to it
+                    let mut end = offset;
+                    if self.body[..end].ends_with(b"\n") {
+                        end -= 1;
+                    }
+                    if self.body[..end].ends_with(b"\r") {
+                        end -= 1;
+                    }
+                    parts.push(Part::parse(&self.body[start..end.max(start)]));
+                }
+                if trimmed[delimiter.len()..].starts_with(b"--") {
+                    break;
+                }
+                start = Some(offset + line.len());
+            }
+            offset += line.len();
+        }
+        parts
+    }
+}
+
+struct Attachment {
+    name: String,
+    mime_type: String,
+    content: Vec<u8>,
+}
+
+#[derive(Default)]
+struct Message {
+    subject: String,
+    from: String,
+    to: String,
+    // utc, like 2024-01-31T08:00:00Z, None when the header can not be parsed
+    date: Option<String>,
+    // the header as it is, shown when it can not be parsed
+    date_header: Option<String>,
+    text: Option<String>,
+    html: Option<String>,
+    attachments: Vec<Attachment>,
+}
+
+impl Message {
+    fn parse(part: &Part) -> Self {
+        let mut message = Message {
+            subject: part.header("subject").unwrap_or("").to_string(),
+            from: part.header("from").unwrap_or("").to_string(),
+            to: part.header("to").unwrap_or("").to_string(),
+            date: part.header("date").and_then(normalize_date),
+            date_header: part.header("date").map(|date| date.trim().to_string()),
+            ..Default::default()
+        };
+        message.walk(part);
+        message
+    }
+
+    fn walk(&mut self, part: &Part) {
+        let (mime_type, _) = part.content_type();
+        if mime_type.starts_with("multipart/") {
+            for child in part.children() {
+                self.walk(&child);
+            }
+            return;
+        }
+        if mime_type == "message/rfc822" {
+            let name = part
+                .file_name()
+                .filter(|n| !n.is_empty())
+                .unwrap_or_else(|| {
+                    let subject = Part::parse(part.body)
+                        .header("subject")
+                        .unwrap_or("")
+                        .to_string();
+                    format!(
+                        "{}.eml",
+                        if subject.is_empty() {
+                            "message"
+                        } else {
+                            &subject
+                        }
+                    )
+                });
+            self.attachments.push(Attachment {
+                name,
+                mime_type,
+                content: part.content(),
+            });
+            return;
+        }
+        match part.file_name() {
+            Some(name) => self.attachments.push(Attachment {
+                name,
+                mime_type,
+                content: part.content(),
+            }),
+            None if mime_type == "text/plain" => {
+                let text = part.text();
+                match self.text.as_mut() {
+                    Some(body) => *body += &("\n\n".to_string() + &text),
+                    None => self.text = Some(text),
+                }
+            }
+            None if mime_type == "text/html" && self.html.is_none() => {
+                self.html = Some(part.text());
+            }
+            // inline images and the like
+            None => {}
+        }
+    }
+
+    fn units(&self, units: &mut Vec<Unit>) {
+        let section = self.subject.clone();
+        let mut header = vec![];
+        if !self.subject.is_empty() {
+            header.push(format!("主题：{}", self.subject));
+        }
+        if !self.from.is_empty() {
+            header.push(format!("发件人：{}", self.from));
+        }
+        if !self.to.is_empty() {
+            header.push(format!("收件人：{}", self.to));
+        }
+        if let Some(date) = self.date.as_ref().or(self.date_header.as_ref()) {
+            header.push(format!("日期：{}", date));
+        }
+        if !header.is_empty() {
+            units.push(Unit {
+                text: header.join("\n"),
+                section: section.clone(),
+            });
+        }
+
+        let body = match (&self.text, &self.html) {
+            (Some(text), _) => text.clone(),
+            (None, Some(html)) => parse_html(html)
+                .units
+                .into_iter()
+                .map(|unit| unit.text)
+                .collect::<Vec<_>>()
+                .join("\n\n"),
+            (None, None) => String::new(),
+        };
+        for paragraph in strip_quotes(&body).split("\n\n") {
+            let paragraph = paragraph.trim();
+            if !paragraph.is_empty() {
+                units.push(Unit {
+                    text: paragraph.to_string(),
+                    section: section.clone(),
+                });
+            }
+        }
+
+        for attachment in &self.attachments {
+            match extract_attachment(attachment) {
+                Ok(document) => units.extend(document.units.into_iter().map(|unit| {
+                    Unit {
+                        section: [section.as_str(), &attachment.name, &unit.section]
+                            .into_iter()
+                            .filter(|s| !s.is_empty())
+                            .collect::<Vec<_>>()
+                            .join(" > "),
+                        text: unit.text,
+                    }
+                })),
+                Err(e) => warn!("skip attachment {}: {}", attachment.name, e),
+            }
+        }
+    }
+}
+
+// by the parser of its content, through a temporary file
+fn extract_attachment(attachment: &Attachment) -> Result<Document> {
+    let name = attachment
+        .name
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or_default()
+        .to_string();
+    let path = std::env::temp_dir().join(format!("qai-{}-{}", rand::random::<u64>(), name));
+    std::fs::write(&path, &attachment.content)?;
+    let document = PARSERS
+        .find(&name, Some(&attachment.mime_type), &path)
+        .and_then(|parser| parser.extract(&path));
+    let _ = std::fs::remove_file(&path);
+    document
+}
+
+// drops quoted lines, and everything from the attribution line of a reply or a forward, and the
+// signature
+fn strip_quotes(body: &str) -> String {
+    let body = body.replace("\r\n", "\n");
+    let lines = body.lines().collect::<Vec<_>>();
+    let mut kept = vec![];
+    for (i, line) in lines.iter().enumerate() {
+        let trimmed = line.trim();
+        if *line == "-- " || *line == "--" {
+            break;
+        }
+        if QUOTE_HEADERS.iter().any(|h| trimmed.starts_with(h))
+            || trimmed.contains("原始邮件") && trimmed.starts_with("---")
+        {
+            break;
+        }
+        // "On Mon, 1 Jan 2024, Alice <alice@example.com> wrote:", may be wrapped over two lines
+        let next = lines.get(i + 1).map(|l| l.trim()).unwrap_or("");
+        let attribution = |a: &str| {
+            a.starts_with("On ") && a.ends_with("wrote:")
+                || a.starts_with('在') && (a.ends_with("写道：") || a.ends_with("写道:"))
+        };
+        if attribution(trimmed) || attribution(&format!("{} {}", trimmed, next)) {
+            break;
+        }
+        // outlook headers of the replied message
+        if (trimmed.starts_with("From:")
+            || trimmed.starts_with("发件人:")
+            || trimmed.starts_with("发件人："))
+            && lines[i + 1..].iter().take(4).any(|l| {
+                let l = l.trim();
+                l.starts_with("Sent:") || l.starts_with("发送时间") || l.starts_with("Date:")
+            })
+        {
+            break;
+        }
+        if trimmed.starts_with('>') {
+            continue;
+        }
+        kept.push(*line);
+    }
+    kept.join("\n")
+}
+
+// by the declared charset, or detected like a text file
+fn decode_text(bytes: &[u8], charset: Option<&str>) -> String {
+    if let Some(encoding) = charset.and_then(|c| Encoding::for_label(c.trim().as_bytes())) {
+        return encoding.decode_without_bom_handling(bytes).0.to_string();
+    }
+    decode(bytes).unwrap_or_else(|_| String::from_utf8_lossy(bytes).to_string())
+}
+
+// rfc 2047 encoded words like =?UTF-8?B?5Lit5paH?=, the space between two of them is dropped
+fn decode_words(value: &str) -> String {
+    let mut decoded = String::new();
+    let mut rest = value;
+    let mut last_was_word = false;
+    while let Some(start) = rest.find("=?") {
+        let word = rest[start + 2..].splitn(3, '?').collect::<Vec<_>>();
+        let parsed = match word.as_slice() {
+            [charset, encoding, tail] => tail.find("?=").map(|end| {
+                let text = &tail[..end];
+                let bytes = match encoding.to_ascii_uppercase().as_str() {
+                    "B" => STANDARD_NO_PAD
+                        .decode(text.trim_end_matches('='))
+                        .unwrap_or_default(),
+                    _ => quoted_printable(text.as_bytes(), true),
+                };
+                let consumed = start + 2 + charset.len() + encoding.len() + 2 + end + 2;
+                // charset may carry a language, like utf-8*zh
+                let charset = charset.split('*').next().unwrap_or(charset);
+                (decode_text(&bytes, Some(charset)), consumed)
+            }),
+            _ => None,
+        };
+        let Some((text, consumed)) = parsed else {
+            decoded += &rest[..start + 2];
+            rest = &rest[start + 2..];
+            last_was_word = false;
+            continue;
+        };
+        let between = &rest[..start];
+        if !(last_was_word && between.trim().is_empty()) {
+            decoded += between;
+        }
+        decoded += &text;
+        rest = &rest[consumed.min(rest.len())..];
+        last_was_word = true;
+    }
+    decoded + rest
+}
+
+// in headers '_' is a space
+fn quoted_printable(bytes: &[u8], header: bool) -> Vec<u8> {
+    let mut decoded = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'=' => {
+                let hex = bytes
+                    .get(i + 1..i + 3)
+                    .and_then(|hex| std::str::from_utf8(hex).ok())
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                if let Some(byte) = hex {
+                    decoded.push(byte);
+                    i += 3;
+                } else if bytes[i + 1..].starts_with(b"\r\n") {
+                    // soft line break
+                    i += 3;
+                } else if bytes[i + 1..].starts_with(b"\n") {
+                    i += 2;
+                } else {
+                    decoded.push(b'=');
+                    i += 1;
+                }
+            }
+            b'_' if header => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            byte => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    decoded
+}
+
+// value; key=value; key="quoted value", rfc 2231 key*=charset''percent-encoded and key*0, key*1
+// continuations are joined
+fn parameters(header: &str) -> (String, BTreeMap<String, String>) {
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    for c in header.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                field.push(c);
+            }
+            ';' if !quoted => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    fields.push(field);
+    let value = fields.remove(0).trim().to_string();
+
+    // name => [(section, extended, value)]
+    let mut sections: BTreeMap<String, Vec<(usize, bool, String)>> = BTreeMap::new();
+    for field in fields {
+        let Some((key, value)) = field.split_once('=') else {
+            continue;
+        };
+        let key = key.trim().to_lowercase();
+        let value = value.trim().trim_matches('"').to_string();
+        let extended = key.ends_with('*');
+        let key = key.trim_end_matches('*');
+        let (name, section) = match key.split_once('*') {
+            Some((name, section)) => (name.to_string(), section.parse::<usize>().unwrap_or(0)),
+            None => (key.to_string(), 0),
+        };
+        sections
+            .entry(name)
+            .or_default()
+            .push((section, extended, value));
+    }
+    let mut params = BTreeMap::new();
+    for (name, mut parts) in sections {
+        parts.sort_by_key(|(section, _, _)| *section);
+        let mut charset = None;
+        let mut bytes = vec![];
+        for (section, extended, value) in parts {
+            if !extended {
+                bytes.extend_from_slice(value.as_bytes());
+                continue;
+            }
+            let mut value = value.as_str();
+            if section == 0 {
+                // charset'language'value
+                let mut fields = value.splitn(3, '\'');
+                if let (Some(c), Some(_), Some(rest)) =
+                    (fields.next(), fields.next(), fields.next())
+                {
+                    charset = Some(c.to_string());
+                    value = rest;
+                }
+            }
+            bytes.extend(percent_decode(value));
+        }
+        params.insert(name, decode_text(&bytes, charset.as_deref()));
+    }
+    (value, params)
+}
+
+fn normalize_date(date: &str) -> Option<String> {
+    // a trailing comment like "(CST)" is not rfc 2822
+    let trimmed = match date.find('(') {
+        Some(i) => date[..i].trim(),
+        None => date.trim(),
+    };
+    chrono::DateTime::parse_from_rfc2822(trimmed)
+        .ok()
+        .map(|date| {
+            date.with_timezone(&chrono::Utc)
+                .format("%Y-%m-%dT%H:%M:%SZ")
+                .to_string()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_file::test_util::temp_file;
+
+    #[test]
+    fn decodes_encoded_words() {
+        assert_eq!(decode_words("=?UTF-8?B?5Lit5paH?="), "中文");
+        assert_eq!(decode_words("=?utf-8?q?a_b=3Dc?="), "a b=c");
+        assert_eq!(decode_words("=?gbk?Q?=B2=E2=CA=D4?= mail"), "测试 mail");
+        // the space between two words is dropped, other text is kept
+        assert_eq!(
+            decode_words("Re: =?UTF-8?B?5Lit?= =?UTF-8?B?5paH?= <a@b.c>"),
+            "Re: 中文 <a@b.c>"
+        );
+        // rfc 2231 language tag
+        assert_eq!(decode_words("=?utf-8*zh?B?5Lit5paH?= ok"), "中文 ok");
+        assert_eq!(decode_words("=?broken"), "=?broken");
+        assert_eq!(decode_words("plain"), "plain");
+    }
+
+    #[test]
+    fn parses_parameters() {
+        let (value, params) = parameters(r#"text/plain; charset="utf-8"; name="a;b.txt""#);
+        assert_eq!(value, "text/plain");
+        assert_eq!(params["charset"], "utf-8");
+        assert_eq!(params["name"], "a;b.txt");
+
+        let (value, params) = parameters(
+            "attachment; filename*0*=UTF-8''%E5%B9%B4%E5%BA%A6; filename*1*=%20%E6%8A%A5%E5%91%8A.pdf",
+        );
+        assert_eq!(value, "attachment");
+        assert_eq!(params["filename"], "年度 报告.pdf");
+    }
+
+    #[test]
+    fn strips_quotes_and_signatures() {
+        let body = "Thanks!\r\n> old text\r\nSee below.\r\n-- \r\nAlice";
+        assert_eq!(strip_quotes(body), "Thanks!\nSee below.");
+        let reply = "好的\n\n在 2024年1月1日，Bob 写道：\n> 原文";
+        assert_eq!(strip_quotes(reply), "好的\n");
+        let wrapped = "Sure.\nOn Mon, 1 Jan 2024, Bob <bob@example.com>\nwrote:\nold";
+        assert_eq!(strip_quotes(wrapped), "Sure.");
+        let outlook = "Done.\nFrom: Bob\nSent: Monday\nSubject: x";
+        assert_eq!(strip_quotes(outlook), "Done.");
+    }
+
+    #[test]
+    fn splits_mbox() {
+        let mbox = b"From a@b.c Mon Jan  1 00:00:00 2024\nSubject: one\n\nbody\n>From here\n\nFrom b@b.c Tue Jan  2 00:00:00 2024\nSubject: two\n\nsecond\n";
+        let messages = split_mbox(mbox);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0], b"Subject: one\n\nbody\nFrom here\n\n");
+        assert_eq!(split_mbox(b"Subject: x\n\nbody").len(), 1);
+    }
+
+    #[test]
+    fn normalizes_dates() {
+        assert_eq!(
+            normalize_date("Mon, 1 Jan 2024 16:00:00 +0800 (CST)").as_deref(),
+            Some("2024-01-01T08:00:00Z")
+        );
+        assert_eq!(normalize_date("2024年3月1日"), None);
+    }
+
+    #[test]
+    fn extracts_messages_and_attachments() {
+        let mbox = "From a@b.c Mon Jan  1 00:00:00 2024\n\
+Subject: =?UTF-8?B?5Li76aKY77ya5oql5ZGK?=\n\
+From: Alice <alice@example.com>\n\
+To: team@example.com\n\
+Date: Mon, 1 Jan 2024 16:00:00 +0800\n\
+Content-Type: multipart/mixed; boundary=\"xx\"\n\
+\n\
+--xx\n\
+Content-Type: text/plain; charset=utf-8\n\
+\n\
+Hello team\n\
+> quoted\n\
+--xx\n\
+Content-Type: text/plain; name=\"notes.txt\"\n\
+Content-Disposition: attachment; filename=\"notes.txt\"\n\
+Content-Transfer-Encoding: base64\n\
+\n\
+6ZmE5Lu25q2j5paH\n\
+--xx--\n\
+\n\
+From b@b.c Wed Jan  3 00:00:00 2024\n\
+Subject: second\n\
+From: Bob <bob@example.com>\n\
+Date: Wed, 3 Jan 2024 10:00:00 +0000\n\
+\n\
+Bye\n";
+        let path = temp_file("a.mbox", mbox.as_bytes());
+        let document = EmailParser.extract(&path).unwrap();
+
+        assert_eq!(document.metadata["title"], "主题：报告");
+        assert_eq!(
+            document.metadata["from"],
+            "Alice <alice@example.com>; Bob <bob@example.com>"
+        );
+        assert_eq!(document.metadata["date"], "2024-01-01T08:00:00Z");
+        assert_eq!(document.metadata["date_end"], "2024-01-03T10:00:00Z");
+        let texts = document
+            .units
+            .iter()
+            .map(|u| (u.section.as_str(), u.text.as_str()))
+            .collect::<Vec<_>>();
+        assert!(texts[0].1.starts_with("主题：主题：报告\n发件人：Alice"));
+        assert!(texts.contains(&("主题：报告", "Hello team")));
+        assert!(texts.iter().any(
+            |(section, text)| section.starts_with("主题：报告 > notes.txt") && *text == "附件正文"
+        ));
+        assert!(texts.contains(&("second", "Bye")));
+        assert!(!texts.iter().any(|(_, text)| text.contains("quoted")));
+    }
+}
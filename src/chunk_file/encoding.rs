@@ -0,0 +1,251 @@
+// text encodings of uploaded files, decoded to utf-8 before parsing
+//
+// utf-8, with or without BOM, and utf-16 with BOM, or without BOM when every other byte is 0,
+// and gb18030 (a superset of gbk and gb2312) of files exported by legacy chinese windows tools
+
+use super::Document;
+use encoding_rs::{DecoderResult, GB18030};
+use std::path::Path;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    Gb18030,
+}
+
+impl Encoding {
+    // recorded as the "encoding" metadata of text documents
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "utf-8",
+            Encoding::Utf16Le => "utf-16le",
+            Encoding::Utf16Be => "utf-16be",
+            Encoding::Gb18030 => "gb18030",
+        }
+    }
+}
+
+// None for binary content, head may end in the middle of a char
+pub fn detect(head: &[u8]) -> Option<Encoding> {
+    if head.starts_with(&[0xEF, 0xBB, 0xBF]) {
+        return Some(Encoding::Utf8);
+    }
+    if head.starts_with(&[0xFF, 0xFE]) {
+        return Some(Encoding::Utf16Le);
+    }
+    if head.starts_with(&[0xFE, 0xFF]) {
+        return Some(Encoding::Utf16Be);
+    }
+    if !head.contains(&0) {
+        return match std::str::from_utf8(head) {
+            Ok(_) => Some(Encoding::Utf8),
+            Err(e) if e.error_len().is_none() => Some(Encoding::Utf8),
+            Err(_) if is_gb18030(head) => Some(Encoding::Gb18030),
+            Err(_) => None,
+        };
+    }
+    // ascii heavy utf-16 without BOM, zeros on one side only
+    let pairs = head.len() / 2;
+    if pairs == 0 {
+        return None;
+    }
+    let even = head.iter().step_by(2).filter(|b| **b == 0).count();
+    let odd = head.iter().skip(1).step_by(2).filter(|b| **b == 0).count();
+    if odd * 10 > pairs * 3 && even == 0 {
+        Some(Encoding::Utf16Le)
+    } else if even * 10 > pairs * 3 && odd == 0 {
+        Some(Encoding::Utf16Be)
+    } else {
+        None
+    }
+}
+
+// valid gb18030 reading mostly as chinese, binary content decodes to control chars or rare ones
+fn is_gb18030(head: &[u8]) -> bool {
+    let mut decoder = GB18030.new_decoder_without_bom_handling();
+    let Some(capacity) = decoder.max_utf8_buffer_length_without_replacement(head.len()) else {
+        return false;
+    };
+    let mut text = String::with_capacity(capacity);
+    // not the last piece, head may end in the middle of a char
+    let (result, _) = decoder.decode_to_string_without_replacement(head, &mut text, false);
+    if result != DecoderResult::InputEmpty {
+        return false;
+    }
+    let (mut chars, mut controls, mut non_ascii, mut chinese) = (0, 0, 0, 0);
+    for c in text.chars() {
+        chars += 1;
+        if c.is_control() && !matches!(c, '\t' | '\n' | '\r' | '\x0c') {
+            controls += 1;
+        }
+        if !c.is_ascii() {
+            non_ascii += 1;
+        }
+        // cjk symbols and punctuation, unified ideographs, fullwidth forms
+        if matches!(c, '\u{3000}'..='\u{303F}' | '\u{4E00}'..='\u{9FFF}' | '\u{FF00}'..='\u{FFEF}')
+        {
+            chinese += 1;
+        }
+    }
+    controls * 100 <= chars && chinese * 2 >= non_ascii
+}
+
+pub fn decode(bytes: &[u8]) -> anyhow::Result<String> {
+    decode_detected(bytes).map(|(text, _)| text)
+}
+
+// the text and the encoding it was decoded from
+pub fn decode_detected(bytes: &[u8]) -> anyhow::Result<(String, Encoding)> {
+    let Some(encoding) = detect(bytes) else {
+        return Err(anyhow::anyhow!("not a text file"));
+    };
+    let text = match encoding {
+        Encoding::Utf8 => {
+            let bytes = bytes.strip_prefix(&[0xEF, 0xBB, 0xBF]).unwrap_or(bytes);
+            String::from_utf8(bytes.to_vec())?
+        }
+        Encoding::Utf16Le => {
+            let bytes = bytes.strip_prefix(&[0xFF, 0xFE]).unwrap_or(bytes);
+            decode_utf16(bytes, u16::from_le_bytes)?
+        }
+        Encoding::Utf16Be => {
+            let bytes = bytes.strip_prefix(&[0xFE, 0xFF]).unwrap_or(bytes);
+            decode_utf16(bytes, u16::from_be_bytes)?
+        }
+        Encoding::Gb18030 => GB18030
+            .decode_without_bom_handling_and_without_replacement(bytes)
+            .ok_or(anyhow::anyhow!("invalid gb18030 text"))?
+            .to_string(),
+    };
+    Ok((text, encoding))
+}
+
+// a text file decoded and parsed, the encoding is recorded as metadata
+pub fn read_document(
+    path: &Path,
+    parse: impl FnOnce(&str) -> anyhow::Result<Document>,
+) -> anyhow::Result<Document> {
+    let (text, encoding) = decode_detected(&std::fs::read(path)?)?;
+    let mut document = parse(&text)?;
+    document
+        .metadata
+        .insert("encoding".to_string(), encoding.name().to_string());
+    Ok(document)
+}
+
+fn decode_utf16(bytes: &[u8], from_bytes: fn([u8; 2]) -> u16) -> anyhow::Result<String> {
+    let units = bytes
+        .chunks_exact(2)
+        .map(|pair| from_bytes([pair[0], pair[1]]))
+        .collect::<Vec<_>>();
+    Ok(String::from_utf16(&units)?)
+}
+
+// %XX escapes of urls and rfc 2231 parameters
+pub fn percent_decode(value: &str) -> Vec<u8> {
+    let bytes = value.as_bytes();
+    let mut decoded = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = (bytes[i] == b'%')
+            .then(|| value.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match hex {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utf16(text: &str, big_endian: bool) -> Vec<u8> {
+        text.encode_utf16()
+            .flat_map(|u| {
+                if big_endian {
+                    u.to_be_bytes()
+                } else {
+                    u.to_le_bytes()
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn detects_utf8() {
+        assert_eq!(detect(b"plain ascii"), Some(Encoding::Utf8));
+        assert_eq!(detect("中文".as_bytes()), Some(Encoding::Utf8));
+        assert_eq!(detect(b"\xEF\xBB\xBFbom"), Some(Encoding::Utf8));
+        // the head ends in the middle of a char
+        assert_eq!(detect(&"中文".as_bytes()[..4]), Some(Encoding::Utf8));
+        assert_eq!(decode(b"\xEF\xBB\xBFbom").unwrap(), "bom");
+    }
+
+    #[test]
+    fn detects_utf16() {
+        let le = [&[0xFF, 0xFE][..], &utf16("中文 text", false)].concat();
+        assert_eq!(
+            decode_detected(&le).unwrap(),
+            ("中文 text".to_string(), Encoding::Utf16Le)
+        );
+        let be = [&[0xFE, 0xFF][..], &utf16("中文 text", true)].concat();
+        assert_eq!(
+            decode_detected(&be).unwrap(),
+            ("中文 text".to_string(), Encoding::Utf16Be)
+        );
+        // ascii heavy text without BOM
+        let le = utf16("hello world, 你好", false);
+        assert_eq!(
+            decode_detected(&le).unwrap(),
+            ("hello world, 你好".to_string(), Encoding::Utf16Le)
+        );
+        let be = utf16("hello world", true);
+        assert_eq!(detect(&be), Some(Encoding::Utf16Be));
+    }
+
+    #[test]
+    fn detects_gb18030() {
+        let text = "第一行：中文内容，测试GBK编码。\r\n第二行 ascii 123\r\n";
+        let (bytes, _, _) = GB18030.encode(text);
+        assert_eq!(detect(&bytes), Some(Encoding::Gb18030));
+        assert_eq!(
+            decode_detected(&bytes).unwrap(),
+            (text.to_string(), Encoding::Gb18030)
+        );
+        // cut in the middle of a two byte char
+        assert_eq!(detect(&bytes[..5]), Some(Encoding::Gb18030));
+        assert_eq!(Encoding::Gb18030.name(), "gb18030");
+    }
+
+    #[test]
+    fn rejects_binary() {
+        let binary = (0..=255u8).cycle().take(4096).collect::<Vec<_>>();
+        assert_eq!(detect(&binary), None);
+        assert!(decode(&binary).is_err());
+        // valid gb18030 in theory, but not chinese text
+        let noise = [
+            0x81, 0x30, 0x81, 0x30, 0x82, 0x31, 0x82, 0x31, 0xFE, 0x39, 0x84, 0x30,
+        ];
+        assert!(!is_gb18030(&noise));
+        assert_eq!(detect(&[0x00, 0x01, 0x00, 0x00, 0x02, 0x00]), None);
+    }
+
+    #[test]
+    fn percent_decodes() {
+        assert_eq!(percent_decode("a%20b%E4%B8%AD"), "a b中".as_bytes());
+        assert_eq!(percent_decode("100%"), b"100%");
+        assert_eq!(percent_decode("%zz"), b"%zz");
+    }
+}
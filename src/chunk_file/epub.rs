@@ -0,0 +1,181 @@
+use super::encoding::percent_decode;
+use super::html::parse_html;
+use super::parser::DocumentParser;
+use super::xml::{attr, read_entry, reference, resolve};
+use super::{Document, Unit};
+use anyhow::Result;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::path::Path;
+use zip::ZipArchive;
+
+pub struct EpubParser;
+
+impl DocumentParser for EpubParser {
+    fn name(&self) -> &'static str {
+        "EPUB"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["epub"]
+    }
+
+    fn mime_types(&self) -> &'static [&'static str] {
+        &["application/epub+zip"]
+    }
+
+    // chapters in reading order, parsed like html, a chapter without headings takes its title
+    // as the section
+    fn extract(&self, path: &Path) -> Result<Document> {
+        let mut archive = ZipArchive::new(File::open(path)?)?;
+        let container = read_entry(&mut archive, "META-INF/container.xml")?;
+        let opf = rootfile(&container)?.ok_or(anyhow::anyhow!("epub without a package file"))?;
+        let package = Package::parse(&read_entry(&mut archive, &opf)?)?;
+        let dir = opf.rsplit_once('/').map(|(dir, _)| dir).unwrap_or("");
+
+        let mut units = vec![];
+        for id in &package.spine {
+            let Some((href, media_type)) = package.manifest.get(id) else {
+                continue;
+            };
+            if !media_type.contains("html") {
+                continue;
+            }
+            // hrefs are urls, names of the zip entries are not
+            let href = percent_decode(href.split('#').next().unwrap_or(href));
+            let href = String::from_utf8_lossy(&href);
+            let name = resolve(dir, &href);
+            let chapter = parse_html(&read_entry(&mut archive, &name)?);
+            let title = chapter.metadata.get("title").cloned().unwrap_or_default();
+            units.extend(chapter.units.into_iter().map(|unit| Unit {
+                section: if unit.section.is_empty() {
+                    title.clone()
+                } else {
+                    unit.section
+                },
+                text: unit.text,
+            }));
+        }
+
+        let mut metadata = BTreeMap::new();
+        if let Some(title) = package.title {
+            metadata.insert("title".to_string(), title);
+        }
+        Ok(Document { units, metadata })
+    }
+}
+
+// path of the package (.opf) file
+fn rootfile(container: &str) -> Result<Option<String>> {
+    let mut reader = Reader::from_str(container);
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) | Event::Empty(e) if e.local_name().as_ref() == b"rootfile" => {
+                if let Some(path) = attr(&e, "full-path") {
+                    return Ok(Some(path));
+                }
+            }
+            Event::Eof => return Ok(None),
+            _ => {}
+        }
+    }
+}
+
+#[derive(Default)]
+struct Package {
+    title: Option<String>,
+    // id => (href, media type)
+    manifest: HashMap<String, (String, String)>,
+    // ids of the manifest items in reading order
+    spine: Vec<String>,
+}
+
+impl Package {
+    fn parse(opf: &str) -> Result<Self> {
+        let mut reader = Reader::from_str(opf);
+        let mut package = Package::default();
+        let mut title: Option<String> = None;
+        loop {
+            match reader.read_event()? {
+                Event::Start(e) if e.local_name().as_ref() == b"title" => {
+                    title = Some(String::new())
+                }
+                Event::End(e) if e.local_name().as_ref() == b"title" => {
+                    let title = title.take().unwrap_or_default().trim().to_string();
+                    if package.title.is_none() && !title.is_empty() {
+                        package.title = Some(title);
+                    }
+                }
+                Event::Text(text) => {
+                    if let Some(title) = title.as_mut() {
+                        *title += &text.xml_content()?;
+                    }
+                }
+                Event::GeneralRef(e) => {
+                    if let Some(title) = title.as_mut() {
+                        *title += &reference(&e)?;
+                    }
+                }
+                Event::Start(e) | Event::Empty(e) => match e.local_name().as_ref() {
+                    b"item" => {
+                        if let (Some(id), Some(href), Some(media_type)) =
+                            (attr(&e, "id"), attr(&e, "href"), attr(&e, "media-type"))
+                        {
+                            package.manifest.insert(id, (href, media_type));
+                        }
+                    }
+                    b"itemref" => {
+                        if let Some(id) = attr(&e, "idref") {
+                            package.spine.push(id);
+                        }
+                    }
+                    _ => {}
+                },
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+        Ok(package)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_file::test_util::zip_file;
+
+    #[test]
+    fn chapters_in_spine_order() {
+        let container = r#"<container><rootfiles><rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/></rootfiles></container>"#;
+        let opf = r#"<package xmlns:dc="dc"><metadata><dc:title>Guide &amp; notes</dc:title></metadata>
+            <manifest>
+            <item id="intro" href="text/intro.xhtml" media-type="application/xhtml+xml"/>
+            <item id="usage" href="text/chapter%202.xhtml#top" media-type="application/xhtml+xml"/>
+            <item id="css" href="style.css" media-type="text/css"/>
+            </manifest>
+            <spine><itemref idref="usage"/><itemref idref="css"/><itemref idref="missing"/><itemref idref="intro"/></spine></package>"#;
+        let intro = "<html><head><title>Intro</title></head><body><p>Welcome</p></body></html>";
+        let usage = "<html><head><title>Usage</title></head><body><h1>Run</h1><p>Start it</p></body></html>";
+        let path = zip_file(
+            "a.epub",
+            &[
+                ("mimetype", b"application/epub+zip"),
+                ("META-INF/container.xml", container.as_bytes()),
+                ("OEBPS/content.opf", opf.as_bytes()),
+                ("OEBPS/text/intro.xhtml", intro.as_bytes()),
+                ("OEBPS/text/chapter 2.xhtml", usage.as_bytes()),
+                ("OEBPS/style.css", b"p {}"),
+            ],
+        );
+        let document = EpubParser.extract(&path).unwrap();
+        assert_eq!(document.metadata["title"], "Guide & notes");
+        let units = document
+            .units
+            .iter()
+            .map(|u| (u.section.as_str(), u.text.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(units, vec![("Run", "Start it"), ("Intro", "Welcome")]);
+    }
+}
@@ -0,0 +1,374 @@
+use super::encoding::read_document;
+use super::parser::DocumentParser;
+use super::{Document, Unit};
+use anyhow::Result;
+use scraper::{ElementRef, Html, Selector};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+// never content: scripts, styles, and the navigation around a wiki page
+const SKIPPED_TAGS: [&str; 12] = [
+    "script", "style", "noscript", "template", "iframe", "svg", "canvas", "form", "button", "nav",
+    "footer", "aside",
+];
+// a header in these holds the title of the content, elsewhere it is the banner of the page
+const CONTENT_TAGS: [&str; 3] = ["article", "main", "section"];
+const SKIPPED_ROLES: [&str; 4] = ["navigation", "banner", "contentinfo", "complementary"];
+// class or id of boilerplate blocks, matched as a word of the attribute
+const SKIPPED_NAMES: [&str; 8] = [
+    "nav",
+    "navbar",
+    "menu",
+    "sidebar",
+    "breadcrumb",
+    "breadcrumbs",
+    "footer",
+    "toc",
+];
+const BLOCK_TAGS: [&str; 14] = [
+    "p",
+    "div",
+    "section",
+    "article",
+    "main",
+    "blockquote",
+    "dl",
+    "dt",
+    "dd",
+    "figure",
+    "figcaption",
+    "address",
+    "details",
+    "summary",
+];
+
+pub struct HtmlParser;
+
+impl DocumentParser for HtmlParser {
+    fn name(&self) -> &'static str {
+        "HTML"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["html", "htm", "xhtml"]
+    }
+
+    fn mime_types(&self) -> &'static [&'static str] {
+        &["text/html", "application/xhtml+xml"]
+    }
+
+    fn text_based(&self) -> bool {
+        true
+    }
+
+    fn extract(&self, path: &Path) -> Result<Document> {
+        read_document(path, |text| Ok(parse_html(text)))
+    }
+}
+
+// headings make the section like markdown, one unit per block, list item or table row
+// metadata: title, url (canonical link, or og:url)
+pub fn parse_html(text: &str) -> Document {
+    let html = Html::parse_document(text);
+    let mut metadata = BTreeMap::new();
+    let first_attr = |selector: &str, attr: &str| {
+        let selector = Selector::parse(selector).unwrap();
+        html.select(&selector)
+            .find_map(|e| e.value().attr(attr).map(|v| v.trim().to_string()))
+            .filter(|v| !v.is_empty())
+    };
+    let title = Selector::parse("title").unwrap();
+    if let Some(title) = html.select(&title).next() {
+        let title = collapse(&title.text().collect::<String>());
+        if !title.is_empty() {
+            metadata.insert("title".to_string(), title);
+        }
+    }
+    if let Some(url) = first_attr("link[rel=canonical]", "href")
+        .or_else(|| first_attr("meta[property=\"og:url\"]", "content"))
+    {
+        metadata.insert("url".to_string(), url);
+    }
+
+    let body = Selector::parse("body").unwrap();
+    let root = html.select(&body).next().unwrap_or(html.root_element());
+    let mut walker = Walker::default();
+    walker.walk(root);
+    walker.flush();
+    Document {
+        units: walker.units,
+        metadata,
+    }
+}
+
+#[derive(Default)]
+struct Walker {
+    units: Vec<Unit>,
+    // (level, title) of the enclosing headings
+    headings: Vec<(usize, String)>,
+    block: String,
+    // list item marker already in block, a block of only the marker is empty
+    marker: String,
+    // next number of each enclosing list, None for bullet lists
+    lists: Vec<Option<usize>>,
+}
+
+impl Walker {
+    fn walk(&mut self, element: ElementRef) {
+        for child in element.children() {
+            if let Some(text) = child.value().as_text() {
+                let text = collapse_keep_edges(text);
+                if self.block.is_empty() || self.block.ends_with([' ', '\n']) {
+                    self.block += text.trim_start();
+                } else {
+                    self.block += &text;
+                }
+                continue;
+            }
+            let Some(child) = ElementRef::wrap(child) else {
+                continue;
+            };
+            let tag = child.value().name();
+            if skipped(child) {
+                continue;
+            }
+            match tag {
+                "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                    self.flush();
+                    let level = tag[1..].parse::<usize>().unwrap();
+                    self.headings.retain(|(l, _)| *l < level);
+                    let title = collapse(&child.text().collect::<String>());
+                    self.headings.push((level, title));
+                }
+                "ul" | "ol" => {
+                    self.flush();
+                    self.lists.push((tag == "ol").then_some(1));
+                    self.walk(child);
+                    self.flush();
+                    self.lists.pop();
+                }
+                "li" => {
+                    self.flush();
+                    let indent = "  ".repeat(self.lists.len().saturating_sub(1));
+                    self.marker = match self.lists.last_mut() {
+                        Some(Some(n)) => {
+                            *n += 1;
+                            format!("{}{}. ", indent, *n - 1)
+                        }
+                        _ => format!("{}- ", indent),
+                    };
+                    self.block += &self.marker.clone();
+                    self.walk(child);
+                    self.flush();
+                }
+                "table" => {
+                    self.flush();
+                    self.table(child);
+                }
+                "pre" => {
+                    self.flush();
+                    self.block = child.text().collect::<String>();
+                    self.flush();
+                }
+                "br" => self.block += "\n",
+                _ if BLOCK_TAGS.contains(&tag) => {
+                    self.flush();
+                    self.walk(child);
+                    self.flush();
+                }
+                _ => self.walk(child),
+            }
+        }
+    }
+
+    // one unit per row, cells joined by " | ", nested tables are flattened into their cell
+    fn table(&mut self, table: ElementRef) {
+        let rows = Selector::parse("tr").unwrap();
+        let cells = Selector::parse("th, td").unwrap();
+        let own = |element: &ElementRef, tag: &str| {
+            element
+                .ancestors()
+                .find(|a| a.value().as_element().is_some_and(|e| e.name() == tag))
+                .map(|a| a.id())
+        };
+        for row in table
+            .select(&rows)
+            .filter(|row| own(row, "table") == Some(table.id()))
+        {
+            let line = row
+                .select(&cells)
+                .filter(|cell| own(cell, "tr") == Some(row.id()))
+                .map(|cell| collapse(&cell.text().collect::<String>()))
+                .collect::<Vec<_>>()
+                .join(" | ");
+            self.block = line;
+            self.flush();
+        }
+    }
+
+    fn flush(&mut self) {
+        let text = self
+            .block
+            .lines()
+            .map(|l| l.trim_end())
+            .collect::<Vec<_>>()
+            .join("\n");
+        let text = text.trim_matches('\n');
+        if !text.trim().is_empty() && text.trim() != self.marker.trim() {
+            let section = self
+                .headings
+                .iter()
+                .map(|(_, title)| title.as_str())
+                .filter(|title| !title.is_empty())
+                .collect::<Vec<_>>()
+                .join(" > ");
+            self.units.push(Unit {
+                text: text.to_string(),
+                section,
+            });
+        }
+        self.block.clear();
+        self.marker.clear();
+    }
+}
+
+fn skipped(element: ElementRef) -> bool {
+    let value = element.value();
+    if SKIPPED_TAGS.contains(&value.name()) || value.attr("hidden").is_some() {
+        return true;
+    }
+    if value.name() == "header"
+        && !element.ancestors().any(|a| {
+            a.value()
+                .as_element()
+                .is_some_and(|e| CONTENT_TAGS.contains(&e.name()))
+        })
+    {
+        return true;
+    }
+    if value
+        .attr("role")
+        .is_some_and(|role| SKIPPED_ROLES.contains(&role))
+    {
+        return true;
+    }
+    let names = value.classes().chain(value.id());
+    names
+        .flat_map(|name| name.split(['-', '_']))
+        .any(|word| SKIPPED_NAMES.contains(&word.to_lowercase().as_str()))
+}
+
+fn collapse(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+// like collapse, but keeps a single space at either end so that inline elements stay apart
+fn collapse_keep_edges(text: &str) -> String {
+    let collapsed = collapse(text);
+    if collapsed.is_empty() {
+        return if text.is_empty() { "" } else { " " }.to_string();
+    }
+    let start = if text.starts_with(char::is_whitespace) {
+        " "
+    } else {
+        ""
+    };
+    let end = if text.ends_with(char::is_whitespace) {
+        " "
+    } else {
+        ""
+    };
+    start.to_string() + &collapsed + end
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_file::test_util::temp_file;
+
+    fn units(document: &Document) -> Vec<(&str, &str)> {
+        document
+            .units
+            .iter()
+            .map(|u| (u.section.as_str(), u.text.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn skips_boilerplate() {
+        let document = parse_html(
+            r#"<html><body>
+            <header><a href="/">Wiki</a></header>
+            <nav><a>Home</a></nav>
+            <div class="sidebar-left">links</div>
+            <div role="navigation">more links</div>
+            <script>var x = 1;</script>
+            <main><p>Content</p></main>
+            <footer>Copyright</footer>
+            </body></html>"#,
+        );
+        assert_eq!(units(&document), vec![("", "Content")]);
+    }
+
+    #[test]
+    fn keeps_headers_of_content() {
+        let document = parse_html(
+            r#"<body><article><header><h1>Release</h1><p>by Alice</p></header>
+            <p>Notes</p></article></body>"#,
+        );
+        assert_eq!(
+            units(&document),
+            vec![("Release", "by Alice"), ("Release", "Notes")]
+        );
+    }
+
+    #[test]
+    fn headings_lists_and_tables() {
+        let document = parse_html(
+            r#"<body><h1>Guide</h1><p>Intro <b>bold</b>
+            text</p><h2>Install</h2><ol><li>Download</li><li>Run<ul><li>twice</li></ul></li></ol>
+            <h2>Options</h2><table><tr><th>Name</th><th>Default</th></tr>
+            <tr><td>port</td><td>8080</td></tr></table><pre>a
+  b</pre></body>"#,
+        );
+        assert_eq!(
+            units(&document),
+            vec![
+                ("Guide", "Intro bold text"),
+                ("Guide > Install", "1. Download"),
+                ("Guide > Install", "2. Run"),
+                ("Guide > Install", "  - twice"),
+                ("Guide > Options", "Name | Default"),
+                ("Guide > Options", "port | 8080"),
+                ("Guide > Options", "a\n  b"),
+            ]
+        );
+    }
+
+    #[test]
+    fn title_and_url() {
+        let document = parse_html(
+            r#"<html><head><title> Release
+            notes </title><meta property="og:url" content="https://b.c/og">
+            <link rel="canonical" href="https://b.c/notes"></head><body><p>x</p></body></html>"#,
+        );
+        assert_eq!(document.metadata["title"], "Release notes");
+        assert_eq!(document.metadata["url"], "https://b.c/notes");
+        let document = parse_html(
+            r#"<html><head><meta property="og:url" content="https://b.c/og"></head></html>"#,
+        );
+        assert_eq!(document.metadata["url"], "https://b.c/og");
+        assert!(!document.metadata.contains_key("title"));
+    }
+
+    #[test]
+    fn decodes_gbk_pages() {
+        let html = "<html><head><title>发布说明</title></head><body><h1>安装</h1><p>下载后运行</p></body></html>";
+        let (bytes, _, _) = encoding_rs::GBK.encode(html);
+        let path = temp_file("a.html", &bytes);
+        let document = HtmlParser.extract(&path).unwrap();
+        assert_eq!(document.metadata["title"], "发布说明");
+        assert_eq!(document.metadata["encoding"], "gb18030");
+        assert_eq!(units(&document), vec![("安装", "下载后运行")]);
+    }
+}
@@ -0,0 +1,177 @@
+use super::encoding::read_document;
+use super::parser::DocumentParser;
+use super::{Document, Unit};
+use anyhow::Result;
+use pulldown_cmark::{Event, Options, Parser, Tag, TagEnd};
+use std::path::Path;
+
+pub struct MarkdownParser;
+
+impl DocumentParser for MarkdownParser {
+    fn name(&self) -> &'static str {
+        "MARKDOWN"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["md", "markdown"]
+    }
+
+    fn mime_types(&self) -> &'static [&'static str] {
+        &["text/markdown"]
+    }
+
+    fn text_based(&self) -> bool {
+        true
+    }
+
+    fn extract(&self, path: &Path) -> Result<Document> {
+        read_document(path, |text| Ok(Document::from(parse_markdown(text))))
+    }
+}
+
+// one unit per paragraph, list item, code block or table row, headings only make the section
+pub fn parse_markdown(text: &str) -> Vec<Unit> {
+    let mut units = vec![];
+    // (level, title) of the enclosing headings
+    let mut headings: Vec<(usize, String)> = vec![];
+    let mut heading: Option<String> = None;
+    let mut block = String::new();
+    // list item marker already in block, a block of only the marker is empty
+    let mut marker = String::new();
+    // next number of each enclosing list, None for bullet lists
+    let mut lists: Vec<Option<u64>> = vec![];
+    let mut row: Vec<String> = vec![];
+
+    let section = |headings: &[(usize, String)]| {
+        headings
+            .iter()
+            .map(|(_, title)| title.as_str())
+            .filter(|title| !title.is_empty())
+            .collect::<Vec<_>>()
+            .join(" > ")
+    };
+    let mut flush = |block: &mut String, marker: &mut String, headings: &[(usize, String)]| {
+        let text = block.trim_end();
+        if !text.trim().is_empty() && text.trim() != marker.trim() {
+            units.push(Unit {
+                text: text.to_string(),
+                section: section(headings),
+            });
+        }
+        block.clear();
+        marker.clear();
+    };
+
+    for event in Parser::new_ext(text, Options::ENABLE_TABLES) {
+        match event {
+            Event::Start(Tag::Heading { .. }) => {
+                flush(&mut block, &mut marker, &headings);
+                heading = Some(String::new());
+            }
+            Event::End(TagEnd::Heading(level)) => {
+                let level = level as usize;
+                headings.retain(|(l, _)| *l < level);
+                let title = heading.take().unwrap_or_default();
+                headings.push((level, title.trim().to_string()));
+            }
+            Event::Start(Tag::List(start)) => {
+                flush(&mut block, &mut marker, &headings);
+                lists.push(start);
+            }
+            Event::End(TagEnd::List(_)) => {
+                flush(&mut block, &mut marker, &headings);
+                lists.pop();
+            }
+            Event::Start(Tag::Item) => {
+                flush(&mut block, &mut marker, &headings);
+                let indent = "  ".repeat(lists.len().saturating_sub(1));
+                marker = match lists.last_mut() {
+                    Some(Some(n)) => {
+                        *n += 1;
+                        format!("{}{}. ", indent, *n - 1)
+                    }
+                    _ => format!("{}- ", indent),
+                };
+                block += &marker;
+            }
+            Event::Start(Tag::CodeBlock(_)) => flush(&mut block, &mut marker, &headings),
+            Event::End(TagEnd::Paragraph)
+            | Event::End(TagEnd::Item)
+            | Event::End(TagEnd::CodeBlock)
+            | Event::End(TagEnd::BlockQuote(_)) => flush(&mut block, &mut marker, &headings),
+            Event::End(TagEnd::TableCell) => {
+                row.push(block.trim().replace('\n', " "));
+                block.clear();
+            }
+            Event::End(TagEnd::TableHead) | Event::End(TagEnd::TableRow) => {
+                block = row.join(" | ");
+                row.clear();
+                flush(&mut block, &mut marker, &headings);
+            }
+            Event::Text(text) | Event::Code(text) => match heading.as_mut() {
+                Some(heading) => *heading += &text,
+                None => block += &text,
+            },
+            Event::SoftBreak | Event::HardBreak => match heading.as_mut() {
+                Some(heading) => *heading += " ",
+                None => block += "\n",
+            },
+            _ => {}
+        }
+    }
+    flush(&mut block, &mut marker, &headings);
+    units
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn units(text: &str) -> Vec<(String, String)> {
+        parse_markdown(text)
+            .into_iter()
+            .map(|u| (u.section, u.text))
+            .collect()
+    }
+
+    fn pair(section: &str, text: &str) -> (String, String) {
+        (section.to_string(), text.to_string())
+    }
+
+    #[test]
+    fn heading_path_is_the_section() {
+        let text = "intro\n\n# 安装\n\nfirst\nline\n\n## 配置\n\nsecond\n\n# Usage\n\nthird\n";
+        assert_eq!(
+            units(text),
+            vec![
+                pair("", "intro"),
+                pair("安装", "first\nline"),
+                pair("安装 > 配置", "second"),
+                pair("Usage", "third"),
+            ]
+        );
+    }
+
+    #[test]
+    fn lists_code_and_tables() {
+        let text = "# A\n\n- one\n- two\n  1. x\n  2. y\n\n```\nlet a = 1;\n```\n\n| k | v |\n|---|---|\n| a | `b` |\n";
+        assert_eq!(
+            units(text),
+            vec![
+                pair("A", "- one"),
+                pair("A", "- two"),
+                pair("A", "  1. x"),
+                pair("A", "  2. y"),
+                pair("A", "let a = 1;"),
+                pair("A", "k | v"),
+                pair("A", "a | b"),
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_items_and_headings() {
+        assert_eq!(units("-\n- \n"), vec![]);
+        assert_eq!(units("# \n\ntext\n"), vec![pair("", "text")]);
+    }
+}
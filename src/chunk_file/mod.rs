@@ -1,37 +1,34 @@
+pub mod archive;
 pub mod docx;
+pub mod email;
+pub mod encoding;
+pub mod epub;
+pub mod html;
+pub mod markdown;
 pub mod normal;
+pub mod odt;
+pub mod parser;
 pub mod pdf;
+pub mod pptx;
+pub mod rtf;
+pub mod spreadsheet;
+#[cfg(test)]
+mod test_util;
+pub mod xml;
 
-use self::{docx::parse_docx, normal::parse_normal, pdf::parse_pdf};
+use self::parser::{DocumentParser, PARSERS};
 use crate::CHUNK_TOKENS;
 use anyhow::Result;
-use async_openai::types::EmbeddingInput;
-use std::{fmt::Display, path::PathBuf};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt::Display, path::PathBuf};
 use tiktoken_rs::cl100k_base;
 
-#[derive(PartialEq, Clone)]
-pub enum FileType {
-    Pdf,
-    Docx,
-    Normal,
-}
-
-impl Display for FileType {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            FileType::Pdf => write!(f, "PDF"),
-            FileType::Docx => write!(f, "DOCX"),
-            FileType::Normal => write!(f, "NORMAL"),
-        }
-    }
-}
-
 #[derive(Clone)]
 pub struct UnlearnedFile {
     pub file_name: String,
     pub uploader: String,
     pub path: PathBuf,
-    pub file_type: FileType,
+    pub parser: &'static dyn DocumentParser,
 }
 
 impl Display for UnlearnedFile {
@@ -39,7 +36,7 @@ impl Display for UnlearnedFile {
         write!(
             f,
             "{} {{ file_name: {}, uploader: {}, path: {} }}",
-            self.file_type,
+            self.parser.name(),
             self.file_name,
             self.uploader,
             self.path.display()
@@ -47,98 +44,180 @@ impl Display for UnlearnedFile {
     }
 }
 
-pub fn match_file(file_name: String, uploader: String, path: PathBuf) -> UnlearnedFile {
-    let file_type = if file_name.ends_with(".pdf") {
-        FileType::Pdf
-    } else if file_name.ends_with(".docx") {
-        FileType::Docx
-    } else {
-        FileType::Normal
-    };
-    UnlearnedFile {
+// the file must be written already, files of unknown extensions are sniffed
+pub fn match_file(
+    file_name: String,
+    uploader: String,
+    path: PathBuf,
+    mime_type: Option<&str>,
+) -> Result<UnlearnedFile> {
+    let parser = PARSERS.find(&file_name, mime_type, &path)?;
+    Ok(UnlearnedFile {
         file_name,
         uploader,
         path,
-        file_type,
+        parser,
+    })
+}
+
+// location units are pdf pages, slides, sheets of spreadsheets, or paragraphs for other files
+// the extracted text is all units joined by '\n', offsets count chars in it
+#[derive(Default, Clone, Debug)]
+pub struct Unit {
+    pub text: String,
+    // heading path like "安装 > 配置 > 代理", empty for formats without structure
+    // a chunk never spans two sections
+    pub section: String,
+}
+
+impl From<String> for Unit {
+    fn from(text: String) -> Self {
+        Unit {
+            text,
+            section: String::new(),
+        }
+    }
+}
+
+// what the units of a file are, decides how the location of a chunk is shown
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum UnitKind {
+    Page,
+    #[default]
+    Paragraph,
+    // one unit per sheet, one line per record, the sheet name is the section
+    Sheet,
+    Slide,
+}
+
+impl UnitKind {
+    // old knowledges do not record it
+    pub fn guess(file_name: &str) -> Self {
+        if file_name.ends_with(".pdf") {
+            UnitKind::Page
+        } else {
+            UnitKind::Paragraph
+        }
+    }
+}
+
+// what a parser extracts from a file
+#[derive(Default, Clone, Debug)]
+pub struct Document {
+    pub units: Vec<Unit>,
+    // e.g. title, url, stored with the knowledge
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl From<Vec<Unit>> for Document {
+    fn from(units: Vec<Unit>) -> Self {
+        Document {
+            units,
+            metadata: BTreeMap::new(),
+        }
     }
 }
 
 #[derive(Default, Clone, Debug)]
 pub struct UnLearnedChunk {
     pub content: String,
+    // section of all its units
+    pub section: String,
+    // unit where the chunk starts, 1-based
     pub page: usize,
+    // unit where the chunk ends, 1-based, inclusive
+    pub end_page: usize,
+    // line inside the start unit where the chunk starts, 1-based
+    pub paragraph: usize,
+    // line inside the end unit where the chunk ends, 1-based, inclusive
+    pub end_paragraph: usize,
+    pub char_start: usize,
+    // exclusive
+    pub char_end: usize,
 }
 
 #[derive(Clone)]
 pub struct UnLearnedKnowledge {
     pub file_name: String,
     pub uploader: String,
+    pub metadata: BTreeMap<String, String>,
+    pub unit_kind: UnitKind,
     pub chunks: Vec<UnLearnedChunk>,
 }
 
-impl From<UnlearnedFile> for Result<UnLearnedKnowledge> {
-    fn from(file: UnlearnedFile) -> Result<UnLearnedKnowledge> {
-        match file.file_type {
-            FileType::Pdf => parse_pdf(file),
-            FileType::Docx => parse_docx(file),
-            FileType::Normal => parse_normal(file),
-        }
-    }
+// units of the extracted text, the same as used when the file was chunked
+pub fn extract(file: &UnlearnedFile) -> Result<Document> {
+    file.parser.extract(&file.path)
 }
 
-impl From<UnLearnedKnowledge> for EmbeddingInput {
-    fn from(val: UnLearnedKnowledge) -> Self {
-        EmbeddingInput::StringArray(val.chunks.iter().map(|u| u.content.clone()).collect())
+impl From<UnlearnedFile> for Result<UnLearnedKnowledge> {
+    fn from(file: UnlearnedFile) -> Result<UnLearnedKnowledge> {
+        let document = extract(&file)?;
+        Ok(UnLearnedKnowledge {
+            file_name: file.file_name,
+            uploader: file.uploader,
+            metadata: document.metadata,
+            unit_kind: file.parser.unit_kind(),
+            chunks: chunk(document.units, file.parser.wrap()),
+        })
     }
 }
 
-fn chunk(iter: impl Iterator<Item = String>) -> Vec<UnLearnedChunk> {
+// wrap: split lines longer than this many chars, so that a long paragraph can span chunks
+fn chunk(units: Vec<Unit>, wrap: Option<usize>) -> Vec<UnLearnedChunk> {
     let bpe = cl100k_base().unwrap();
     let mut chunks = Vec::new();
-    let mut legacy = UnLearnedChunk::default();
-    let mut legacy_tokens = 0;
-    for (page, content) in iter.enumerate() {
-        let mut chunk = legacy;
-        let mut current_tokens = legacy_tokens;
-        let lines = content.lines();
-        for line in lines {
-            let new_line = line.to_string() + "\n";
-            if chunk.content.is_empty() {
-                chunk.page = page + 1;
+    let mut chunk = UnLearnedChunk::default();
+    let mut current_tokens = 0;
+    let mut unit_offset = 0;
+    for (page, unit) in units.iter().enumerate() {
+        if !chunk.content.is_empty() && chunk.section != unit.section {
+            chunks.push(chunk);
+            chunk = UnLearnedChunk::default();
+            current_tokens = 0;
+        }
+        let mut offset = unit_offset;
+        let segments = unit.text.split('\n').collect::<Vec<_>>();
+        for (line_index, segment) in segments.iter().enumerate() {
+            // like str::lines, no empty line after a trailing '\n'
+            if line_index + 1 == segments.len() && segment.is_empty() {
+                break;
             }
-            current_tokens += bpe.encode_with_special_tokens(&new_line).len();
-            chunk.content += &new_line;
-            if current_tokens > *CHUNK_TOKENS {
-                chunks.push(chunk);
-                chunk = UnLearnedChunk::default();
-                current_tokens = 0;
+            let line = segment.strip_suffix('\r').unwrap_or(segment);
+            let chars = line.chars().collect::<Vec<_>>();
+            let pieces = match wrap {
+                Some(width) if !chars.is_empty() => chars.chunks(width).collect::<Vec<_>>(),
+                _ => vec![&chars[..]],
+            };
+            let mut piece_offset = offset;
+            for piece in pieces {
+                let new_line = piece.iter().collect::<String>() + "\n";
+                if chunk.content.is_empty() {
+                    chunk.section = unit.section.clone();
+                    chunk.page = page + 1;
+                    chunk.paragraph = line_index + 1;
+                    chunk.char_start = piece_offset;
+                }
+                piece_offset += piece.len();
+                chunk.end_page = page + 1;
+                chunk.end_paragraph = line_index + 1;
+                chunk.char_end = piece_offset;
+                current_tokens += bpe.encode_with_special_tokens(&new_line).len();
+                chunk.content += &new_line;
+                if current_tokens > *CHUNK_TOKENS {
+                    chunks.push(chunk);
+                    chunk = UnLearnedChunk::default();
+                    current_tokens = 0;
+                }
             }
+            offset += segment.chars().count() + 1;
         }
-        legacy = chunk;
-        legacy_tokens = current_tokens;
+        unit_offset += unit.text.chars().count() + 1;
     }
-    if !legacy.content.is_empty() {
-        chunks.push(legacy);
+    if !chunk.content.is_empty() {
+        chunks.push(chunk);
     }
 
     chunks
 }
-
-fn insert_newlines(vec: Vec<String>) -> Vec<String> {
-    let mut new_vec = Vec::new();
-    for para in vec {
-        let mut result = String::new();
-        let mut counter = 0;
-
-        for c in para.chars() {
-            result.push(c);
-            counter += 1;
-
-            if counter % 60 == 0 {
-                result.push('\n');
-            }
-        }
-        new_vec.push(result);
-    }
-    new_vec
-}
@@ -1,21 +1,38 @@
-use super::{chunk, insert_newlines, UnLearnedKnowledge, UnlearnedFile};
+use super::encoding::read_document;
+use super::parser::DocumentParser;
+use super::{Document, Unit};
 use anyhow::Result;
+use std::path::Path;
 
-pub fn parse_normal(file: UnlearnedFile) -> Result<UnLearnedKnowledge> {
-    let content = std::fs::read_to_string(&file.path)?;
-    let content_replace_windows_newline = content.replace("\r\n", "\n");
-    let paras = content_replace_windows_newline
-        .split('\n')
-        .filter(|s| !s.is_empty())
-        .map(|s| s.to_owned())
-        .collect::<Vec<_>>();
-    let paras_with_newlines = insert_newlines(paras);
-    let unlearned_chunk_vec = chunk(paras_with_newlines.into_iter());
+pub struct TextParser;
 
-    let unlearned_knowledge = UnLearnedKnowledge {
-        file_name: file.file_name,
-        uploader: file.uploader,
-        chunks: unlearned_chunk_vec,
-    };
-    Ok(unlearned_knowledge)
+impl DocumentParser for TextParser {
+    fn name(&self) -> &'static str {
+        "TEXT"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["txt", "text", "log"]
+    }
+
+    fn mime_types(&self) -> &'static [&'static str] {
+        &["text/plain"]
+    }
+
+    fn text_based(&self) -> bool {
+        true
+    }
+
+    // one unit per non-empty line
+    fn extract(&self, path: &Path) -> Result<Document> {
+        read_document(path, |content| {
+            let content_replace_windows_newline = content.replace("\r\n", "\n");
+            let paras = content_replace_windows_newline
+                .split('\n')
+                .filter(|s| !s.is_empty())
+                .map(|s| Unit::from(s.to_owned()))
+                .collect::<Vec<_>>();
+            Ok(Document::from(paras))
+        })
+    }
 }
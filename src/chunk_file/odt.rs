@@ -0,0 +1,243 @@
+use super::parser::DocumentParser;
+use super::xml::{attr, read_entry, reference};
+use super::{Document, Unit};
+use anyhow::Result;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::Path;
+use zip::ZipArchive;
+
+// not part of the running text
+const SKIPPED_ELEMENTS: [&str; 4] = ["note", "annotation", "tracked-changes", "sequence-decls"];
+
+pub struct OdtParser;
+
+impl DocumentParser for OdtParser {
+    fn name(&self) -> &'static str {
+        "ODT"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["odt"]
+    }
+
+    fn mime_types(&self) -> &'static [&'static str] {
+        &["application/vnd.oasis.opendocument.text"]
+    }
+
+    // headings make the section like markdown, one unit per paragraph, list item or table row
+    fn extract(&self, path: &Path) -> Result<Document> {
+        let mut archive = ZipArchive::new(File::open(path)?)?;
+        let units = parse_content(&read_entry(&mut archive, "content.xml")?)?;
+        let mut metadata = BTreeMap::new();
+        if archive.index_for_name("meta.xml").is_some() {
+            if let Some(title) = meta_title(&read_entry(&mut archive, "meta.xml")?)? {
+                metadata.insert("title".to_string(), title);
+            }
+        }
+        Ok(Document { units, metadata })
+    }
+}
+
+fn parse_content(xml: &str) -> Result<Vec<Unit>> {
+    let mut reader = Reader::from_str(xml);
+    let mut units = vec![];
+    // (level, title) of the enclosing headings
+    let mut headings: Vec<(usize, String)> = vec![];
+    // level of the heading being read
+    let mut heading: Option<usize> = None;
+    // text:p and text:h nest through frames, the outermost one makes the unit
+    let mut depth = 0;
+    let mut block = String::new();
+    let mut lists: usize = 0;
+    let mut marker = String::new();
+    // cells of the current table row
+    let mut row: Option<Vec<String>> = None;
+    let mut cell = String::new();
+    let mut skipped = 0;
+
+    let section = |headings: &[(usize, String)]| {
+        headings
+            .iter()
+            .map(|(_, title)| title.as_str())
+            .filter(|title| !title.is_empty())
+            .collect::<Vec<_>>()
+            .join(" > ")
+    };
+
+    loop {
+        let event = reader.read_event()?;
+        if skipped > 0 {
+            match event {
+                Event::Start(e) if SKIPPED_ELEMENTS.contains(&local(&e.local_name())) => {
+                    skipped += 1
+                }
+                Event::End(e) if SKIPPED_ELEMENTS.contains(&local(&e.local_name())) => skipped -= 1,
+                Event::Eof => break,
+                _ => {}
+            }
+            continue;
+        }
+        match event {
+            Event::Start(e) => match local(&e.local_name()) {
+                name if SKIPPED_ELEMENTS.contains(&name) => skipped += 1,
+                name @ ("h" | "p") => {
+                    depth += 1;
+                    if depth == 1 && name == "h" {
+                        let level = attr(&e, "text:outline-level")
+                            .and_then(|l| l.parse::<usize>().ok())
+                            .unwrap_or(1);
+                        heading = Some(level);
+                    }
+                }
+                "list" => lists += 1,
+                "list-item" => {
+                    marker = format!("{}- ", "  ".repeat(lists.saturating_sub(1)));
+                }
+                "table-row" => row = Some(vec![]),
+                "table-cell" => cell.clear(),
+                _ => {}
+            },
+            Event::Empty(e) => match local(&e.local_name()) {
+                "s" => {
+                    let count = attr(&e, "text:c")
+                        .and_then(|c| c.parse::<usize>().ok())
+                        .unwrap_or(1);
+                    block += &" ".repeat(count);
+                }
+                "tab" => block += "\t",
+                "line-break" => block += "\n",
+                _ => {}
+            },
+            Event::End(e) => match local(&e.local_name()) {
+                "h" | "p" => {
+                    depth -= 1;
+                    if depth > 0 {
+                        continue;
+                    }
+                    let text = block.trim().to_string();
+                    block.clear();
+                    if let Some(level) = heading.take() {
+                        headings.retain(|(l, _)| *l < level);
+                        headings.push((level, text.replace('\n', " ")));
+                    } else if row.is_some() {
+                        if !text.is_empty() {
+                            if !cell.is_empty() {
+                                cell += " ";
+                            }
+                            cell += &text.replace('\n', " ");
+                        }
+                    } else if !text.is_empty() {
+                        units.push(Unit {
+                            text: marker.clone() + &text,
+                            section: section(&headings),
+                        });
+                        marker.clear();
+                    }
+                }
+                "list" => lists -= 1,
+                "list-item" => marker.clear(),
+                "table-cell" => {
+                    if let Some(row) = row.as_mut() {
+                        row.push(cell.clone());
+                    }
+                }
+                "table-row" => {
+                    let cells = row.take().unwrap_or_default();
+                    if cells.iter().any(|c| !c.is_empty()) {
+                        units.push(Unit {
+                            text: cells.join(" | "),
+                            section: section(&headings),
+                        });
+                    }
+                }
+                _ => {}
+            },
+            Event::Text(text) if depth > 0 => block += &text.xml_content()?,
+            Event::GeneralRef(e) if depth > 0 => block += &reference(&e)?,
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(units)
+}
+
+fn meta_title(xml: &str) -> Result<Option<String>> {
+    let mut reader = Reader::from_str(xml);
+    let mut title: Option<String> = None;
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) if e.name().as_ref() == b"dc:title" => title = Some(String::new()),
+            Event::End(e) if e.name().as_ref() == b"dc:title" => {
+                return Ok(title
+                    .map(|t| t.trim().to_string())
+                    .filter(|t| !t.is_empty()))
+            }
+            Event::Text(text) => {
+                if let Some(title) = title.as_mut() {
+                    *title += &text.xml_content()?;
+                }
+            }
+            Event::GeneralRef(e) => {
+                if let Some(title) = title.as_mut() {
+                    *title += &reference(&e)?;
+                }
+            }
+            Event::Eof => return Ok(None),
+            _ => {}
+        }
+    }
+}
+
+fn local<'a>(name: &'a quick_xml::name::LocalName) -> &'a str {
+    std::str::from_utf8(name.as_ref()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn content(body: &str) -> String {
+        format!(
+            r#"<office:document-content xmlns:office="o" xmlns:text="t" xmlns:table="tb"><office:body><office:text>{}</office:text></office:body></office:document-content>"#,
+            body
+        )
+    }
+
+    #[test]
+    fn sections_lists_and_tables() {
+        let units = parse_content(&content(
+            r#"<text:h text:outline-level="1">Intro</text:h>
+            <text:p>Hello<text:s text:c="2"/>world<text:note><text:p>skipped</text:p></text:note></text:p>
+            <text:h text:outline-level="2">Details</text:h>
+            <text:list><text:list-item><text:p>one</text:p></text:list-item>
+            <text:list-item><text:list><text:list-item><text:p>nested</text:p></text:list-item></text:list></text:list-item></text:list>
+            <table:table><table:table-row><table:table-cell><text:p>a</text:p></table:table-cell><table:table-cell><text:p>b &amp; c</text:p></table:table-cell></table:table-row></table:table>"#,
+        ))
+        .unwrap();
+        let units = units
+            .iter()
+            .map(|u| (u.section.as_str(), u.text.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            units,
+            vec![
+                ("Intro", "Hello  world"),
+                ("Intro > Details", "- one"),
+                ("Intro > Details", "  - nested"),
+                ("Intro > Details", "a | b & c"),
+            ]
+        );
+    }
+
+    #[test]
+    fn list_item_outside_a_list() {
+        let units = parse_content(&content(
+            "<text:list-item><text:p>stray</text:p></text:list-item>",
+        ))
+        .unwrap();
+        assert_eq!(units[0].text, "- stray");
+    }
+}
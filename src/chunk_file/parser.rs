@@ -0,0 +1,238 @@
+// a document format, registered in PARSERS and picked by the content of the file: magic bytes,
+// the parts inside a zip container, or text, in which case the extension or the mime type declared
+// by the uploader picks among the text based formats
+//
+// a parser only extracts units of text (pages, paragraphs, rows...), chunking is the same for
+// every format, see chunk_file::chunk
+
+use super::{
+    docx::DocxParser,
+    email::EmailParser,
+    encoding,
+    epub::EpubParser,
+    html::HtmlParser,
+    markdown::MarkdownParser,
+    normal::TextParser,
+    odt::OdtParser,
+    pdf::PdfParser,
+    pptx::PptxParser,
+    rtf::RtfParser,
+    spreadsheet::{CsvParser, XlsxParser},
+    Document, UnitKind,
+};
+use anyhow::Result;
+use lazy_static::lazy_static;
+use std::io::Read;
+use std::path::Path;
+
+// bytes read to tell the format
+const SNIFF_BYTES: usize = 8192;
+
+pub trait DocumentParser: Send + Sync {
+    // shown in logs, e.g. PDF
+    fn name(&self) -> &'static str;
+
+    // lowercase, without the dot
+    fn extensions(&self) -> &'static [&'static str];
+
+    fn mime_types(&self) -> &'static [&'static str];
+
+    // units of the extracted text, the same every time for the same file
+    fn extract(&self, path: &Path) -> Result<Document>;
+
+    // read from text, the encoding is decoded by chunk_file::encoding
+    fn text_based(&self) -> bool {
+        false
+    }
+
+    fn unit_kind(&self) -> UnitKind {
+        UnitKind::Paragraph
+    }
+
+    // lines longer than this many chars are split when chunking, None keeps them whole
+    fn wrap(&self) -> Option<usize> {
+        Some(60)
+    }
+}
+
+pub struct ParserRegistry {
+    parsers: Vec<&'static dyn DocumentParser>,
+    // text files of unknown extensions
+    text: &'static dyn DocumentParser,
+}
+
+lazy_static! {
+    pub static ref PARSERS: ParserRegistry = ParserRegistry {
+        parsers: vec![
+            &PdfParser,
+            &DocxParser,
+            &PptxParser,
+            &EpubParser,
+            &OdtParser,
+            &RtfParser,
+            &MarkdownParser,
+            &HtmlParser,
+            &EmailParser,
+            &XlsxParser,
+            &CsvParser,
+            &TextParser,
+        ],
+        text: &TextParser,
+    };
+}
+
+impl ParserRegistry {
+    pub fn by_extension(&self, file_name: &str) -> Option<&'static dyn DocumentParser> {
+        let (_, extension) = file_name.rsplit_once('.')?;
+        let extension = extension.to_lowercase();
+        self.parsers
+            .iter()
+            .find(|p| p.extensions().contains(&extension.as_str()))
+            .copied()
+    }
+
+    pub fn by_mime_type(&self, mime_type: &str) -> Option<&'static dyn DocumentParser> {
+        self.parsers
+            .iter()
+            .find(|p| p.mime_types().contains(&mime_type))
+            .copied()
+    }
+
+    // the content decides, the extension and the declared mime type only choose among the
+    // text based formats, unknown binary files are rejected
+    pub fn find(
+        &self,
+        file_name: &str,
+        mime_type: Option<&str>,
+        path: &Path,
+    ) -> Result<&'static dyn DocumentParser> {
+        match sniff(path)? {
+            Content::Typed(detected) => self.by_mime_type(&detected).ok_or(anyhow::anyhow!(
+                "{}: {} is not supported",
+                file_name,
+                detected
+            )),
+            Content::Text => Ok([
+                self.by_extension(file_name),
+                mime_type.and_then(|m| self.by_mime_type(m)),
+            ]
+            .into_iter()
+            .flatten()
+            .find(|p| p.text_based())
+            .unwrap_or(self.text)),
+            Content::Binary => Err(anyhow::anyhow!("{}: unknown binary content", file_name)),
+        }
+    }
+}
+
+pub enum Content {
+    // mime type told by magic bytes or the zip container
+    Typed(String),
+    Text,
+    Binary,
+}
+
+pub fn sniff(path: &Path) -> Result<Content> {
+    let mut head = vec![];
+    std::fs::File::open(path)?
+        .take(SNIFF_BYTES as u64)
+        .read_to_end(&mut head)?;
+    // the header may follow a BOM or blank lines, text merely mentioning it is not a pdf
+    let start = head.strip_prefix(&[0xEF, 0xBB, 0xBF]).unwrap_or(&head);
+    let start = &start[start
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .unwrap_or(start.len())..];
+    if start.starts_with(b"%PDF-") {
+        return Ok(Content::Typed("application/pdf".to_string()));
+    }
+    if head.starts_with(b"{\\rtf") {
+        return Ok(Content::Typed("application/rtf".to_string()));
+    }
+    if head.starts_with(b"PK\x03\x04") {
+        return Ok(Content::Typed(sniff_zip(path)?));
+    }
+    if head.starts_with(&[0xD0, 0xCF, 0x11, 0xE0]) {
+        return Err(anyhow::anyhow!(
+            "legacy office files (doc, xls, ppt) are not supported, please save as docx, xlsx or pptx"
+        ));
+    }
+    if encoding::detect(&head).is_some() {
+        return Ok(Content::Text);
+    }
+    Ok(Content::Binary)
+}
+
+// office open xml is told by its main part, odf and epub carry a `mimetype` entry
+fn sniff_zip(path: &Path) -> Result<String> {
+    let mut archive = zip::ZipArchive::new(std::fs::File::open(path)?)
+        .map_err(|e| anyhow::anyhow!("broken zip file: {}", e))?;
+    if let Ok(entry) = archive.by_name("mimetype") {
+        // a mime type is short, a crafted entry may inflate to gigabytes
+        let mut mime_type = String::new();
+        entry.take(256).read_to_string(&mut mime_type)?;
+        return Ok(mime_type.trim().to_string());
+    }
+    let mime_type = if archive.index_for_name("word/document.xml").is_some() {
+        "application/vnd.openxmlformats-officedocument.wordprocessingml.document"
+    } else if archive.index_for_name("xl/workbook.xml").is_some() {
+        "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"
+    } else if archive.index_for_name("ppt/presentation.xml").is_some() {
+        "application/vnd.openxmlformats-officedocument.presentationml.presentation"
+    } else {
+        "application/zip"
+    };
+    Ok(mime_type.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_file::test_util::{temp_file, zip_file};
+
+    fn parser_name(file_name: &str, path: &Path) -> Result<&'static str> {
+        PARSERS.find(file_name, None, path).map(|p| p.name())
+    }
+
+    #[test]
+    fn pdf_header_only_at_the_start() {
+        let pdf = temp_file("a.pdf", b"\xEF\xBB\xBF\r\n%PDF-1.7\n");
+        assert!(matches!(sniff(&pdf).unwrap(), Content::Typed(t) if t == "application/pdf"));
+        let notes = temp_file("notes.md", b"# PDF\n\nfiles start with %PDF-1.7\n");
+        assert!(matches!(sniff(&notes).unwrap(), Content::Text));
+        assert_eq!(parser_name("notes.md", &notes).unwrap(), "MARKDOWN");
+    }
+
+    #[test]
+    fn text_formats_by_extension() {
+        let csv = temp_file("a.csv", "名称,数量\n苹果,3\n".as_bytes());
+        assert_eq!(parser_name("a.csv", &csv).unwrap(), "CSV");
+        assert_eq!(parser_name("a.unknown", &csv).unwrap(), "TEXT");
+        let rtf = temp_file("a.txt", b"{\\rtf1\\ansi hello}");
+        assert_eq!(parser_name("a.txt", &rtf).unwrap(), "RTF");
+    }
+
+    #[test]
+    fn zip_containers() {
+        let epub = zip_file(
+            "a.epub",
+            &[("mimetype", b"application/epub+zip"), ("a.xhtml", b"")],
+        );
+        assert_eq!(parser_name("a.zip", &epub).unwrap(), "EPUB");
+        let xlsx = zip_file("a.xlsx", &[("xl/workbook.xml", b"")]);
+        assert_eq!(parser_name("a.xlsx", &xlsx).unwrap(), "XLSX");
+        let plain = zip_file("a.zip", &[("readme.txt", b"hello")]);
+        assert!(matches!(sniff(&plain).unwrap(), Content::Typed(t) if t == "application/zip"));
+        let long = zip_file("long.zip", &[("mimetype", &[b'a'; 100000])]);
+        assert!(matches!(sniff(&long).unwrap(), Content::Typed(t) if t.len() == 256));
+    }
+
+    #[test]
+    fn rejects_binary_and_legacy_office() {
+        let ole = temp_file("a.doc", &[0xD0, 0xCF, 0x11, 0xE0, 0xA1, 0xB1, 0x1A, 0xE1]);
+        assert!(parser_name("a.doc", &ole).is_err());
+        let binary = temp_file("a.bin", &[0x00, 0x01, 0x02, 0xFF, 0xFE, 0x00, 0x80, 0x00]);
+        assert!(matches!(sniff(&binary).unwrap(), Content::Binary));
+        assert!(parser_name("a.txt", &binary).is_err());
+    }
+}
@@ -1,19 +1,41 @@
-use super::{chunk, UnLearnedKnowledge, UnlearnedFile};
+use super::parser::DocumentParser;
+use super::{Document, Unit, UnitKind};
 use anyhow::Result;
 use pdfium_render::prelude::Pdfium;
+use std::path::Path;
 
-pub fn parse_pdf(file: UnlearnedFile) -> Result<UnLearnedKnowledge> {
-    let pdfium = Pdfium::new(Pdfium::bind_to_library("./libpdfium.so")?);
-    let pdf_document = pdfium.load_pdf_from_file(&file.path, None)?;
-    let mut pages = vec![];
-    for page in pdf_document.pages().iter() {
-        pages.push(page.text()?.all())
+pub struct PdfParser;
+
+impl DocumentParser for PdfParser {
+    fn name(&self) -> &'static str {
+        "PDF"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["pdf"]
+    }
+
+    fn mime_types(&self) -> &'static [&'static str] {
+        &["application/pdf"]
+    }
+
+    // one unit per page
+    fn extract(&self, path: &Path) -> Result<Document> {
+        let pdfium = Pdfium::new(Pdfium::bind_to_library("./libpdfium.so")?);
+        let pdf_document = pdfium.load_pdf_from_file(path, None)?;
+        let mut pages = vec![];
+        for page in pdf_document.pages().iter() {
+            pages.push(Unit::from(page.text()?.all()))
+        }
+        Ok(pages.into())
+    }
+
+    fn unit_kind(&self) -> UnitKind {
+        UnitKind::Page
+    }
+
+    // lines of a pdf page are short already
+    fn wrap(&self) -> Option<usize> {
+        None
     }
-    let unlearned_chunk_vec = chunk(pages.into_iter());
-    let unlearned_knowledge = UnLearnedKnowledge {
-        file_name: file.file_name,
-        uploader: file.uploader,
-        chunks: unlearned_chunk_vec,
-    };
-    Ok(unlearned_knowledge)
 }
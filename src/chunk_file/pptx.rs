@@ -0,0 +1,270 @@
+use super::parser::DocumentParser;
+use super::xml::{attr, read_entry, reference, resolve};
+use super::{Document, Unit, UnitKind};
+use anyhow::Result;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::Path;
+use zip::ZipArchive;
+
+// placeholders repeated on every slide
+const SKIPPED_PLACEHOLDERS: [&str; 4] = ["sldNum", "dt", "ftr", "hdr"];
+
+pub struct PptxParser;
+
+impl DocumentParser for PptxParser {
+    fn name(&self) -> &'static str {
+        "PPTX"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["pptx"]
+    }
+
+    fn mime_types(&self) -> &'static [&'static str] {
+        &["application/vnd.openxmlformats-officedocument.presentationml.presentation"]
+    }
+
+    fn unit_kind(&self) -> UnitKind {
+        UnitKind::Slide
+    }
+
+    // one unit per slide in show order, empty slides are kept so that numbers match
+    // lines: paragraphs of the shapes, table rows joined by " | ", then the speaker notes
+    fn extract(&self, path: &Path) -> Result<Document> {
+        let mut archive = ZipArchive::new(File::open(path)?)?;
+        let presentation = read_entry(&mut archive, "ppt/presentation.xml")?;
+        let targets = relationships(&mut archive, "ppt/presentation.xml")?;
+        let mut units = vec![];
+        for id in slide_ids(&presentation)? {
+            let Some((_, slide)) = targets.get(&id) else {
+                units.push(Unit::from(String::new()));
+                continue;
+            };
+            let mut lines = shape_text(&read_entry(&mut archive, slide)?, |placeholder| {
+                !placeholder.is_some_and(|p| SKIPPED_PLACEHOLDERS.contains(&p))
+            })?;
+            let notes = relationships(&mut archive, slide)?
+                .into_values()
+                .find(|(kind, _)| kind.ends_with("/notesSlide"));
+            if let Some((_, notes)) = notes {
+                let notes = shape_text(&read_entry(&mut archive, &notes)?, |placeholder| {
+                    placeholder == Some("body")
+                })?;
+                // unprefixed, decks are not all chinese
+                lines.extend(notes);
+            }
+            units.push(Unit::from(lines.join("\n")));
+        }
+        Ok(units.into())
+    }
+}
+
+// relationship id => (type, part name) of a part, empty when it has none
+fn relationships(
+    archive: &mut ZipArchive<File>,
+    part: &str,
+) -> Result<HashMap<String, (String, String)>> {
+    let (dir, file) = part.rsplit_once('/').unwrap_or(("", part));
+    let rels = format!("{}/_rels/{}.rels", dir, file);
+    if archive.index_for_name(&rels).is_none() {
+        return Ok(HashMap::new());
+    }
+    let xml = read_entry(archive, &rels)?;
+    let mut reader = Reader::from_str(&xml);
+    let mut targets = HashMap::new();
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) | Event::Empty(e) if e.local_name().as_ref() == b"Relationship" => {
+                if let (Some(id), Some(kind), Some(target)) =
+                    (attr(&e, "Id"), attr(&e, "Type"), attr(&e, "Target"))
+                {
+                    targets.insert(id, (kind, resolve(dir, &target)));
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(targets)
+}
+
+// relationship ids of the slides in show order
+fn slide_ids(presentation: &str) -> Result<Vec<String>> {
+    let mut reader = Reader::from_str(presentation);
+    let mut ids = vec![];
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) | Event::Empty(e) if e.local_name().as_ref() == b"sldId" => {
+                if let Some(id) = attr(&e, "r:id") {
+                    ids.push(id);
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(ids)
+}
+
+// paragraphs of the shapes whose placeholder type is kept, None for shapes that are not
+// placeholders, a table row is one line
+fn shape_text(xml: &str, keep: impl Fn(Option<&str>) -> bool) -> Result<Vec<String>> {
+    let mut reader = Reader::from_str(xml);
+    let mut lines = vec![];
+    let mut kept = true;
+    let mut in_text = false;
+    let mut paragraph = String::new();
+    // cells of the current table row
+    let mut row: Option<Vec<String>> = None;
+    let mut cell = String::new();
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) => match e.local_name().as_ref() {
+                b"sp" | b"graphicFrame" => kept = keep(None),
+                b"ph" => kept = keep(Some(attr(&e, "type").as_deref().unwrap_or("body"))),
+                b"tr" => row = Some(vec![]),
+                b"tc" => cell.clear(),
+                b"p" => paragraph.clear(),
+                b"t" => in_text = true,
+                _ => {}
+            },
+            Event::Empty(e) => match e.local_name().as_ref() {
+                b"ph" => kept = keep(Some(attr(&e, "type").as_deref().unwrap_or("body"))),
+                b"br" => paragraph += " ",
+                _ => {}
+            },
+            Event::End(e) => match e.local_name().as_ref() {
+                b"t" => in_text = false,
+                b"p" => {
+                    let text = paragraph.trim();
+                    if !kept || text.is_empty() {
+                        continue;
+                    }
+                    if row.is_some() {
+                        if !cell.is_empty() {
+                            cell += " ";
+                        }
+                        cell += text;
+                    } else {
+                        lines.push(text.to_string());
+                    }
+                }
+                b"tc" => {
+                    if let Some(row) = row.as_mut() {
+                        row.push(cell.clone());
+                    }
+                }
+                b"tr" => {
+                    let cells = row.take().unwrap_or_default();
+                    if kept && cells.iter().any(|c| !c.is_empty()) {
+                        lines.push(cells.join(" | "));
+                    }
+                }
+                _ => {}
+            },
+            Event::Text(text) if in_text => paragraph += &text.xml_content()?,
+            Event::GeneralRef(e) if in_text => paragraph += &reference(&e)?,
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_file::test_util::zip_file;
+
+    const RELS: &str = "http://schemas.openxmlformats.org/officeDocument/2006/relationships";
+
+    // shapes of (placeholder type, paragraphs)
+    fn slide(shapes: &[(Option<&str>, &[&str])]) -> String {
+        let shapes = shapes
+            .iter()
+            .map(|(placeholder, paragraphs)| {
+                let placeholder = placeholder
+                    .map(|p| format!(r#"<p:ph type="{}"/>"#, p))
+                    .unwrap_or_default();
+                let paragraphs = paragraphs
+                    .iter()
+                    .map(|p| format!("<a:p><a:r><a:t>{}</a:t></a:r></a:p>", p))
+                    .collect::<String>();
+                format!(
+                    "<p:sp><p:nvSpPr><p:nvPr>{}</p:nvPr></p:nvSpPr><p:txBody>{}</p:txBody></p:sp>",
+                    placeholder, paragraphs
+                )
+            })
+            .collect::<String>();
+        format!(
+            r#"<p:sld xmlns:p="p" xmlns:a="a"><p:cSld><p:spTree>{}</p:spTree></p:cSld></p:sld>"#,
+            shapes
+        )
+    }
+
+    fn rels(targets: &[(&str, &str, &str)]) -> String {
+        let targets = targets
+            .iter()
+            .map(|(id, kind, target)| {
+                format!(
+                    r#"<Relationship Id="{}" Type="{}/{}" Target="{}"/>"#,
+                    id, RELS, kind, target
+                )
+            })
+            .collect::<String>();
+        format!("<Relationships>{}</Relationships>", targets)
+    }
+
+    #[test]
+    fn slides_in_show_order_with_notes() {
+        // the show order differs from the file names, and rId9 points nowhere
+        let presentation = r#"<p:presentation xmlns:p="p" xmlns:r="r"><p:sldIdLst><p:sldId id="256" r:id="rId3"/><p:sldId id="257" r:id="rId9"/><p:sldId id="258" r:id="rId2"/></p:sldIdLst></p:presentation>"#;
+        let presentation_rels = rels(&[
+            ("rId2", "slide", "slides/slide1.xml"),
+            ("rId3", "slide", "slides/slide2.xml"),
+        ]);
+        let first = slide(&[
+            (Some("title"), &["Agenda"]),
+            (None, &["Goals", "Plan &amp; risks"]),
+            (Some("sldNum"), &["1"]),
+        ]);
+        let last = slide(&[(Some("title"), &["Summary"])]);
+        let first_rels = rels(&[("rId1", "notesSlide", "../notesSlides/notesSlide1.xml")]);
+        let notes = slide(&[
+            (Some("sldImg"), &[]),
+            (Some("body"), &["Say hello first"]),
+            (Some("sldNum"), &["1"]),
+        ]);
+        let path = zip_file(
+            "a.pptx",
+            &[
+                ("ppt/presentation.xml", presentation.as_bytes()),
+                (
+                    "ppt/_rels/presentation.xml.rels",
+                    presentation_rels.as_bytes(),
+                ),
+                ("ppt/slides/slide1.xml", last.as_bytes()),
+                ("ppt/slides/slide2.xml", first.as_bytes()),
+                ("ppt/slides/_rels/slide2.xml.rels", first_rels.as_bytes()),
+                ("ppt/notesSlides/notesSlide1.xml", notes.as_bytes()),
+            ],
+        );
+        let document = PptxParser.extract(&path).unwrap();
+        let texts = document
+            .units
+            .iter()
+            .map(|u| u.text.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            texts,
+            vec![
+                "Agenda\nGoals\nPlan & risks\nSay hello first",
+                "",
+                "Summary"
+            ]
+        );
+    }
+}
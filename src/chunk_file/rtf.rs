@@ -0,0 +1,413 @@
+use super::parser::DocumentParser;
+use super::{Document, Unit};
+use anyhow::Result;
+use encoding_rs::Encoding;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+// groups that are not part of the running text
+const SKIPPED_DESTINATIONS: [&str; 22] = [
+    "fonttbl",
+    "colortbl",
+    "stylesheet",
+    "info",
+    "pict",
+    "object",
+    "header",
+    "headerl",
+    "headerr",
+    "headerf",
+    "footer",
+    "footerl",
+    "footerr",
+    "footerf",
+    "footnote",
+    "annotation",
+    "fldinst",
+    "listtable",
+    "listoverridetable",
+    "revtbl",
+    "rsidtbl",
+    "themedata",
+];
+
+pub struct RtfParser;
+
+impl DocumentParser for RtfParser {
+    fn name(&self) -> &'static str {
+        "RTF"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["rtf"]
+    }
+
+    fn mime_types(&self) -> &'static [&'static str] {
+        &["application/rtf", "text/rtf"]
+    }
+
+    fn text_based(&self) -> bool {
+        true
+    }
+
+    fn extract(&self, path: &Path) -> Result<Document> {
+        parse_rtf(&std::fs::read(path)?)
+    }
+}
+
+#[derive(Clone, Default)]
+struct State {
+    skip: bool,
+    // inside \info\title
+    title: bool,
+    // inside \fonttbl, \f defines a font
+    font_table: bool,
+    font: Option<i32>,
+    // chars to skip after \u
+    uc: usize,
+}
+
+// one unit per paragraph or table row, paragraphs with an outline level are headings and make
+// the section, 8-bit text is decoded by the charset of its font or the \ansicpg of the file
+pub fn parse_rtf(bytes: &[u8]) -> Result<Document> {
+    if !bytes.starts_with(b"{\\rtf") {
+        return Err(anyhow::anyhow!("not a rtf file"));
+    }
+    let mut parser = Parser {
+        units: vec![],
+        title: String::new(),
+        headings: vec![],
+        paragraph: String::new(),
+        outline: None,
+        row: vec![],
+        bytes: vec![],
+        skip_chars: 0,
+        ansi: encoding_rs::WINDOWS_1252,
+        fonts: HashMap::new(),
+    };
+    let mut state = State {
+        uc: 1,
+        ..Default::default()
+    };
+    let mut stack: Vec<State> = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'{' => {
+                parser.flush_bytes(&state);
+                stack.push(state.clone());
+                i += 1;
+            }
+            b'}' => {
+                parser.flush_bytes(&state);
+                state = stack.pop().unwrap_or_default();
+                i += 1;
+            }
+            b'\\' => {
+                let Some(&next) = bytes.get(i + 1) else {
+                    break;
+                };
+                if next.is_ascii_alphabetic() {
+                    let start = i + 1;
+                    i = start;
+                    while i < bytes.len() && bytes[i].is_ascii_alphabetic() {
+                        i += 1;
+                    }
+                    let word = std::str::from_utf8(&bytes[start..i]).unwrap_or_default();
+                    let digits = i;
+                    if i < bytes.len() && bytes[i] == b'-' {
+                        i += 1;
+                    }
+                    while i < bytes.len() && bytes[i].is_ascii_digit() {
+                        i += 1;
+                    }
+                    let param = std::str::from_utf8(&bytes[digits..i])
+                        .ok()
+                        .and_then(|p| p.parse::<i32>().ok());
+                    if i < bytes.len() && bytes[i] == b' ' {
+                        i += 1;
+                    }
+                    parser.flush_bytes(&state);
+                    parser.control_word(&mut state, word, param);
+                } else if next == b'\'' {
+                    let byte = bytes
+                        .get(i + 2..i + 4)
+                        .and_then(|hex| std::str::from_utf8(hex).ok())
+                        .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                    if let Some(byte) = byte {
+                        parser.byte(&state, byte);
+                    }
+                    i += 4;
+                } else {
+                    parser.flush_bytes(&state);
+                    match next {
+                        b'*' => state.skip = true,
+                        b'~' => parser.emit(&state, " "),
+                        b'_' => parser.emit(&state, "-"),
+                        b'\\' | b'{' | b'}' => parser.emit(&state, &(next as char).to_string()),
+                        b'\r' | b'\n' => parser.paragraph(&state),
+                        _ => {}
+                    }
+                    i += 2;
+                }
+            }
+            b'\r' | b'\n' => i += 1,
+            byte => {
+                parser.byte(&state, byte);
+                i += 1;
+            }
+        }
+    }
+    parser.flush_bytes(&state);
+    parser.paragraph(&state);
+
+    let mut metadata = BTreeMap::new();
+    let title = parser.title.trim();
+    if !title.is_empty() {
+        metadata.insert("title".to_string(), title.to_string());
+    }
+    Ok(Document {
+        units: parser.units,
+        metadata,
+    })
+}
+
+struct Parser {
+    units: Vec<Unit>,
+    title: String,
+    // (level, title) of the enclosing headings
+    headings: Vec<(usize, String)>,
+    paragraph: String,
+    // outline level of the current paragraph, 0 is the top heading
+    outline: Option<usize>,
+    // cells of the current table row
+    row: Vec<String>,
+    // 8-bit text waiting to be decoded
+    bytes: Vec<u8>,
+    // chars left to skip after \u
+    skip_chars: usize,
+    ansi: &'static Encoding,
+    // font number => charset
+    fonts: HashMap<i32, i32>,
+}
+
+impl Parser {
+    fn control_word(&mut self, state: &mut State, word: &str, param: Option<i32>) {
+        match word {
+            _ if SKIPPED_DESTINATIONS.contains(&word) => {
+                state.skip = true;
+                state.font_table = word == "fonttbl";
+            }
+            "title" => {
+                state.skip = false;
+                state.title = true;
+            }
+            "ansicpg" => {
+                if let Some(encoding) = param.and_then(codepage) {
+                    self.ansi = encoding;
+                }
+            }
+            "f" if state.font_table => state.font = param,
+            "fcharset" if state.font_table => {
+                if let (Some(font), Some(charset)) = (state.font, param) {
+                    self.fonts.insert(font, charset);
+                }
+            }
+            "f" => state.font = param,
+            "uc" => state.uc = param.unwrap_or(1).max(0) as usize,
+            "u" => {
+                if let Some(code) = param {
+                    let code = if code < 0 { code + 65536 } else { code };
+                    if let Some(c) = char::from_u32(code as u32) {
+                        self.emit(state, &c.to_string());
+                    }
+                    self.skip_chars = state.uc;
+                }
+            }
+            "par" | "sect" | "page" => self.paragraph(state),
+            "pard" => self.outline = None,
+            "outlinelevel" => self.outline = param.map(|p| p.max(0) as usize),
+            "line" => self.emit(state, "\n"),
+            "tab" => self.emit(state, "\t"),
+            "emdash" => self.emit(state, "—"),
+            "endash" => self.emit(state, "–"),
+            "bullet" => self.emit(state, "•"),
+            "lquote" => self.emit(state, "‘"),
+            "rquote" => self.emit(state, "’"),
+            "ldblquote" => self.emit(state, "“"),
+            "rdblquote" => self.emit(state, "”"),
+            "cell" if !state.skip => {
+                let cell = self.paragraph.trim().replace('\n', " ");
+                self.row.push(cell);
+                self.paragraph.clear();
+            }
+            "row" if !state.skip => {
+                let row = std::mem::take(&mut self.row);
+                if row.iter().any(|c| !c.is_empty()) {
+                    self.units.push(Unit {
+                        text: row.join(" | "),
+                        section: self.section(),
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn byte(&mut self, state: &State, byte: u8) {
+        if self.skip_chars > 0 {
+            self.skip_chars -= 1;
+            return;
+        }
+        if !state.skip {
+            self.bytes.push(byte);
+        }
+    }
+
+    fn flush_bytes(&mut self, state: &State) {
+        if self.bytes.is_empty() {
+            return;
+        }
+        let encoding = state
+            .font
+            .and_then(|font| self.fonts.get(&font))
+            .and_then(|charset| charset_encoding(*charset))
+            .unwrap_or(self.ansi);
+        let bytes = std::mem::take(&mut self.bytes);
+        let (text, _) = encoding.decode_without_bom_handling(&bytes);
+        self.emit(state, &text);
+    }
+
+    fn emit(&mut self, state: &State, text: &str) {
+        if state.skip {
+            return;
+        }
+        if state.title {
+            self.title += text;
+        } else {
+            self.paragraph += text;
+        }
+    }
+
+    fn paragraph(&mut self, state: &State) {
+        if state.skip {
+            return;
+        }
+        let text = self
+            .paragraph
+            .lines()
+            .map(|l| l.trim_end())
+            .collect::<Vec<_>>()
+            .join("\n");
+        let text = text.trim();
+        if let Some(level) = self.outline {
+            let level = level + 1;
+            self.headings.retain(|(l, _)| *l < level);
+            self.headings.push((level, text.replace('\n', " ")));
+        } else if !text.is_empty() {
+            self.units.push(Unit {
+                text: text.to_string(),
+                section: self.section(),
+            });
+        }
+        self.paragraph.clear();
+    }
+
+    fn section(&self) -> String {
+        self.headings
+            .iter()
+            .map(|(_, title)| title.as_str())
+            .filter(|title| !title.is_empty())
+            .collect::<Vec<_>>()
+            .join(" > ")
+    }
+}
+
+fn codepage(codepage: i32) -> Option<&'static Encoding> {
+    match codepage {
+        936 => Some(encoding_rs::GBK),
+        54936 => Some(encoding_rs::GB18030),
+        950 => Some(encoding_rs::BIG5),
+        932 => Some(encoding_rs::SHIFT_JIS),
+        949 => Some(encoding_rs::EUC_KR),
+        874 => Some(encoding_rs::WINDOWS_874),
+        65001 => Some(encoding_rs::UTF_8),
+        1250..=1258 => Encoding::for_label(format!("windows-{}", codepage).as_bytes()),
+        _ => None,
+    }
+}
+
+// \fcharset of a font, None for the ansi codepage
+fn charset_encoding(charset: i32) -> Option<&'static Encoding> {
+    match charset {
+        128 => codepage(932),
+        129 => codepage(949),
+        134 => codepage(936),
+        136 => codepage(950),
+        161 => codepage(1253),
+        162 => codepage(1254),
+        163 => codepage(1258),
+        177 => codepage(1255),
+        178 => codepage(1256),
+        186 => codepage(1257),
+        204 => codepage(1251),
+        222 => codepage(874),
+        238 => codepage(1250),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn units(rtf: &str) -> Vec<(String, String)> {
+        parse_rtf(rtf.as_bytes())
+            .unwrap()
+            .units
+            .into_iter()
+            .map(|u| (u.section, u.text))
+            .collect()
+    }
+
+    #[test]
+    fn paragraphs_headings_and_title() {
+        let rtf = r"{\rtf1\ansi{\fonttbl{\f0 Arial;}}{\info{\title Report}}
+{\pard\outlinelevel0 Intro\par}
+{\pard First \b bold\b0  line\line next\par}
+{\pard\outlinelevel1 Part\par}
+{\pard Second\par}}";
+        let document = parse_rtf(rtf.as_bytes()).unwrap();
+        assert_eq!(document.metadata["title"], "Report");
+        assert_eq!(
+            units(rtf),
+            vec![
+                ("Intro".to_string(), "First bold line\nnext".to_string()),
+                ("Intro > Part".to_string(), "Second".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn unicode_and_codepages() {
+        // \u with a fallback char, gbk bytes of the ansi codepage and of a charset 134 font
+        let rtf = r"{\rtf1\ansi\ansicpg936{\fonttbl{\f0\fcharset0 Arial;}{\f1\fcharset134 SimSun;}}
+\f0 \u20013?\u25991? \'d6\'d0 {\f1 \'ce\'c4}\par}";
+        assert_eq!(units(rtf), vec![(String::new(), "中文 中 文".to_string())]);
+    }
+
+    #[test]
+    fn tables_and_skipped_groups() {
+        let rtf = r"{\rtf1\ansi{\*\generator Writer;}{\header page}
+\trowd\intbl a\cell b\cell\row
+{\footnote note}text \{braces\}\par}";
+        assert_eq!(
+            units(rtf),
+            vec![
+                (String::new(), "a | b".to_string()),
+                (String::new(), "text {braces}".to_string()),
+            ]
+        );
+        assert!(parse_rtf(b"hello").is_err());
+    }
+}
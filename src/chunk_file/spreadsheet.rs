@@ -0,0 +1,229 @@
+use super::encoding::read_document;
+use super::parser::DocumentParser;
+use super::{Document, Unit, UnitKind};
+use anyhow::Result;
+use calamine::{open_workbook_auto, Data, Reader};
+use std::path::Path;
+
+pub struct XlsxParser;
+
+impl DocumentParser for XlsxParser {
+    fn name(&self) -> &'static str {
+        "XLSX"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["xlsx"]
+    }
+
+    fn mime_types(&self) -> &'static [&'static str] {
+        &["application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"]
+    }
+
+    fn unit_kind(&self) -> UnitKind {
+        UnitKind::Sheet
+    }
+
+    // a record is never split
+    fn wrap(&self) -> Option<usize> {
+        None
+    }
+
+    // one unit per sheet, the first non-empty row is the header
+    fn extract(&self, path: &Path) -> Result<Document> {
+        let mut workbook = open_workbook_auto(path)?;
+        let mut units = vec![];
+        for name in workbook.sheet_names() {
+            let range = workbook.worksheet_range(&name)?;
+            let rows = range
+                .rows()
+                .map(|row| row.iter().map(cell).collect::<Vec<_>>())
+                .collect::<Vec<_>>();
+            let text = records(rows);
+            if !text.is_empty() {
+                units.push(Unit {
+                    text,
+                    section: name,
+                });
+            }
+        }
+        Ok(units.into())
+    }
+}
+
+pub struct CsvParser;
+
+impl DocumentParser for CsvParser {
+    fn name(&self) -> &'static str {
+        "CSV"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["csv", "tsv"]
+    }
+
+    fn mime_types(&self) -> &'static [&'static str] {
+        &["text/csv", "text/tab-separated-values"]
+    }
+
+    fn text_based(&self) -> bool {
+        true
+    }
+
+    fn unit_kind(&self) -> UnitKind {
+        UnitKind::Sheet
+    }
+
+    fn wrap(&self) -> Option<usize> {
+        None
+    }
+
+    // a single unit without section, the first row is the header
+    fn extract(&self, path: &Path) -> Result<Document> {
+        read_document(path, parse_csv)
+    }
+}
+
+fn parse_csv(text: &str) -> Result<Document> {
+    let first_line = text.lines().next().unwrap_or_default();
+    // max_by_key returns the last of equal counts, a tie goes to the comma
+    let delimiter = [b';', b'\t', b',']
+        .into_iter()
+        .max_by_key(|d| first_line.matches(*d as char).count())
+        .unwrap();
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(text.trim_start_matches('\u{feff}').as_bytes());
+    let mut rows = vec![];
+    for record in reader.records() {
+        rows.push(record?.iter().map(clean).collect::<Vec<_>>());
+    }
+    let text = records(rows);
+    let mut document = Document::default();
+    if !text.is_empty() {
+        document.units.push(Unit::from(text));
+    }
+    Ok(document)
+}
+
+// one line per data row like "型号: X; 价格: Y", empty cells and rows are skipped
+fn records(rows: Vec<Vec<String>>) -> String {
+    let mut rows = rows
+        .into_iter()
+        .filter(|row| row.iter().any(|c| !c.is_empty()));
+    let Some(header) = rows.next() else {
+        return String::new();
+    };
+    let mut lines = vec![];
+    for row in rows {
+        let line = row
+            .iter()
+            .enumerate()
+            .filter(|(_, value)| !value.is_empty())
+            .map(|(i, value)| match header.get(i).filter(|h| !h.is_empty()) {
+                Some(name) => format!("{}: {}", name, value),
+                None => format!("列{}: {}", i + 1, value),
+            })
+            .collect::<Vec<_>>()
+            .join("; ");
+        lines.push(line);
+    }
+    lines.join("\n")
+}
+
+fn cell(data: &Data) -> String {
+    match data {
+        Data::DateTime(date) => match date.as_datetime() {
+            Some(time) if time.time() == chrono::NaiveTime::MIN => {
+                time.format("%Y-%m-%d").to_string()
+            }
+            Some(time) => time.format("%Y-%m-%d %H:%M:%S").to_string(),
+            None => clean(&data.to_string()),
+        },
+        _ => clean(&data.to_string()),
+    }
+}
+
+// a record is one line
+fn clean(value: &str) -> String {
+    value.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_file::test_util::{temp_file, zip_file};
+
+    fn csv(text: &str) -> Vec<String> {
+        let path = temp_file("a.csv", text.as_bytes());
+        let document = CsvParser.extract(&path).unwrap();
+        document.units.into_iter().map(|u| u.text).collect()
+    }
+
+    #[test]
+    fn csv_delimiters() {
+        assert_eq!(csv("a;b;c\n1;2;3\n"), vec!["a: 1; b: 2; c: 3"]);
+        assert_eq!(csv("a\tb\n1\t2\n"), vec!["a: 1; b: 2"]);
+        // a tie goes to the comma
+        assert_eq!(csv("a,b;c\n1,2;3\n"), vec!["a: 1; b;c: 2;3"]);
+    }
+
+    #[test]
+    fn csv_records() {
+        assert_eq!(
+            csv("\u{feff}名称,说明,\n苹果,\"红色, 甜\",x\n\n香蕉,,\n"),
+            vec!["名称: 苹果; 说明: 红色, 甜; 列3: x\n名称: 香蕉"]
+        );
+        assert!(csv("名称,数量\n").is_empty());
+    }
+
+    fn sheet(rows: &[&[&str]]) -> String {
+        let rows = rows
+            .iter()
+            .map(|row| {
+                let cells = row
+                    .iter()
+                    .map(|c| format!(r#"<c t="inlineStr"><is><t>{}</t></is></c>"#, c))
+                    .collect::<String>();
+                format!("<row>{}</row>", cells)
+            })
+            .collect::<String>();
+        format!(
+            r#"<worksheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main"><sheetData>{}</sheetData></worksheet>"#,
+            rows
+        )
+    }
+
+    #[test]
+    fn xlsx_sheets() {
+        let workbook = r#"<workbook xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships"><sheets><sheet name="价格" sheetId="1" r:id="rId1"/><sheet name="空" sheetId="2" r:id="rId2"/><sheet name="库存" sheetId="3" r:id="rId3"/></sheets></workbook>"#;
+        let rels = r#"<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet" Target="worksheets/sheet1.xml"/><Relationship Id="rId2" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet" Target="worksheets/sheet2.xml"/><Relationship Id="rId3" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet" Target="worksheets/sheet3.xml"/></Relationships>"#;
+        let prices = sheet(&[&["型号", "价格"], &["A1", "100"], &["B2", "200"]]);
+        let stock = sheet(&[&["型号", "数量"], &["A1", "3"]]);
+        let path = zip_file(
+            "a.xlsx",
+            &[
+                ("xl/workbook.xml", workbook.as_bytes()),
+                ("xl/_rels/workbook.xml.rels", rels.as_bytes()),
+                ("xl/worksheets/sheet1.xml", prices.as_bytes()),
+                ("xl/worksheets/sheet2.xml", sheet(&[]).as_bytes()),
+                ("xl/worksheets/sheet3.xml", stock.as_bytes()),
+            ],
+        );
+        let document = XlsxParser.extract(&path).unwrap();
+        let units = document
+            .units
+            .iter()
+            .map(|u| (u.section.as_str(), u.text.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            units,
+            vec![
+                ("价格", "型号: A1; 价格: 100\n型号: B2; 价格: 200"),
+                ("库存", "型号: A1; 数量: 3"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,48 @@
+// fixtures shared by the parser tests, files live in a temporary directory removed on drop
+
+use std::io::Write;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+pub struct TempFile {
+    path: PathBuf,
+    _dir: TempDir,
+}
+
+impl Deref for TempFile {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AsRef<Path> for TempFile {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+// the name is kept, parsers look at the extension
+pub fn temp_file(name: &str, bytes: &[u8]) -> TempFile {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join(name);
+    std::fs::write(&path, bytes).unwrap();
+    TempFile { path, _dir: dir }
+}
+
+pub fn zip_file(name: &str, entries: &[(&str, &[u8])]) -> TempFile {
+    temp_file(name, &zip_bytes(entries))
+}
+
+pub fn zip_bytes(entries: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut writer = zip::ZipWriter::new(std::io::Cursor::new(vec![]));
+    for (entry, bytes) in entries {
+        writer
+            .start_file(*entry, zip::write::SimpleFileOptions::default())
+            .unwrap();
+        writer.write_all(bytes).unwrap();
+    }
+    writer.finish().unwrap().into_inner()
+}
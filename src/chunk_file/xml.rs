@@ -0,0 +1,81 @@
+// helpers for the xml parts inside zip containers (pptx, epub, odt)
+
+use anyhow::Result;
+use quick_xml::events::{BytesRef, BytesStart};
+use std::fs::File;
+use std::io::Read;
+use zip::ZipArchive;
+
+// far beyond any real part, a small zip may still inflate to gigabytes
+const MAX_ENTRY_BYTES: u64 = 64 * 1024 * 1024;
+
+pub fn read_entry(archive: &mut ZipArchive<File>, name: &str) -> Result<String> {
+    let entry = archive
+        .by_name(name)
+        .map_err(|e| anyhow::anyhow!("{}: {}", name, e))?;
+    read_limited(entry, MAX_ENTRY_BYTES).map_err(|e| anyhow::anyhow!("{}: {}", name, e))
+}
+
+fn read_limited(reader: impl Read, limit: u64) -> Result<String> {
+    let mut text = String::new();
+    reader.take(limit + 1).read_to_string(&mut text)?;
+    if text.len() as u64 > limit {
+        return Err(anyhow::anyhow!("larger than {} bytes", limit));
+    }
+    Ok(text)
+}
+
+// by qualified name, e.g. "r:id"
+pub fn attr(element: &BytesStart, name: &str) -> Option<String> {
+    element
+        .attributes()
+        .flatten()
+        .find(|a| a.key.as_ref() == name.as_bytes())
+        .and_then(|a| {
+            let value = std::str::from_utf8(&a.value).ok()?;
+            quick_xml::escape::unescape(value)
+                .ok()
+                .map(|v| v.to_string())
+        })
+}
+
+// text of a character or predefined entity reference, empty for unknown entities
+pub fn reference(reference: &BytesRef) -> Result<String> {
+    if let Some(c) = reference.resolve_char_ref()? {
+        return Ok(c.to_string());
+    }
+    Ok(
+        quick_xml::escape::resolve_predefined_entity(&reference.decode()?)
+            .unwrap_or_default()
+            .to_string(),
+    )
+}
+
+// entry name of a link target relative to dir
+pub fn resolve(dir: &str, target: &str) -> String {
+    if let Some(absolute) = target.strip_prefix('/') {
+        return absolute.to_string();
+    }
+    let mut parts = dir.split('/').filter(|p| !p.is_empty()).collect::<Vec<_>>();
+    for segment in target.split('/') {
+        match segment {
+            ".." => {
+                parts.pop();
+            }
+            "." | "" => {}
+            _ => parts.push(segment),
+        }
+    }
+    parts.join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_are_limited() {
+        assert_eq!(read_limited("<a/>".as_bytes(), 4).unwrap(), "<a/>");
+        assert!(read_limited("<a></a>".as_bytes(), 4).is_err());
+    }
+}
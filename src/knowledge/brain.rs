@@ -1,20 +1,38 @@
-use super::matching::{match_final, match_top_n};
+use super::cache::EmbeddingCache;
+use super::citation::{mark_cited, render_passages, render_sources, snippet};
+use super::conversation::{render_history, Conversation, Turn};
+use super::event::{QueryEvent, Source, Timings, Usage};
+use super::expansion::expand;
+use super::filter::Filter;
+use super::language::{detect, Language};
+use super::matching::{cosine_similarity, match_final, match_ranked, match_top_n, merge_top_n};
+use super::openai::{context_window, OpenAI};
+use super::prompt::{Prompt, PromptTemplate, PromptVars};
 use super::storage::Storage;
-use crate::chunk_file::UnLearnedKnowledge;
-use crate::{CHUNK_HEAD, CHUNK_TAIL};
+use super::usage::{Ledger, UsageKind, UsageMeter};
+use crate::chunk_file::{UnLearnedChunk, UnLearnedKnowledge, UnitKind};
+use crate::{CHAT_MODEL, CHUNK_HEAD, CHUNK_TAIL, HISTORY_TOKENS, QUERY_EXPANSION, QUERY_VARIANTS};
 use anyhow::Result;
 use async_openai::types::{
-    ChatCompletionRequestMessageArgs, CreateChatCompletionRequestArgs, Role,
+    ChatCompletionRequestMessageArgs, CreateChatCompletionRequestArgs, CreateEmbeddingRequestArgs,
+    Role,
 };
-use async_openai::{types::CreateEmbeddingRequestArgs, Client};
-use futures::stream::SplitSink;
-use futures::{SinkExt, StreamExt};
-use std::collections::HashMap;
+use futures::{Sink, SinkExt, StreamExt};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
 use std::error::Error;
 use std::sync::Arc;
 use std::time::Instant;
 use tokio::sync::{RwLock, Semaphore};
-use warp::ws::{Message, WebSocket};
+use tokio_util::sync::CancellationToken;
+
+const EMBEDDING_MODEL: &str = "text-embedding-ada-002";
+const CANCELLED: &str = "cancelled";
+// max tokens of the answer, reserved in the context window
+const ANSWER_TOKENS: u16 = 1200;
+const REWRITE_PROMPT_ZH: &str =
+    "请根据对话历史，将后续问题改写为一个不依赖上下文、含义完整的独立问题。只输出改写后的问题。";
+const REWRITE_PROMPT_EN: &str = "Rewrite the follow-up question into a standalone question with complete meaning that does not depend on the conversation history. Output the rewritten question only.";
 
 #[derive(Clone)]
 pub struct BrainMetadata {
@@ -26,34 +44,93 @@ pub struct BrainMetadata {
 pub struct Knowledge {
     list: Vec<String>,
     vectors: HashMap<usize, Vec<Vec<f32>>>,
+    // document metadata of each index, for filters
+    metadata: HashMap<usize, BTreeMap<String, String>>,
+}
+
+#[derive(Serialize)]
+pub struct Prepared {
+    pub prompt: Prompt,
+    pub language: Language,
+    // passages of the prompt in citation order
+    pub sources: Vec<Source>,
+    // the query or one of its expansions which found the matched chunk
+    pub variant: String,
+}
+
+#[derive(Serialize)]
+pub struct SearchHit {
+    pub file_name: String,
+    pub index: usize,
+    pub chunk: usize,
+    pub section: String,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
+    pub unit_kind: UnitKind,
+    pub page: usize,
+    pub end_page: usize,
+    pub paragraph: usize,
+    pub end_paragraph: usize,
+    pub char_start: usize,
+    pub char_end: usize,
+    pub score: f32,
+    pub cosine: f32,
+    pub lexical: f32,
+    pub content: String,
+    // neighbouring chunks, CHUNK_HEAD before and CHUNK_TAIL after
+    pub before: String,
+    pub after: String,
 }
 
 #[derive(Clone)]
 pub(crate) struct Brain {
     pub _metadata: BrainMetadata,
-    openai_client: Client,
+    templates: HashMap<Language, PromptTemplate>,
+    openai: OpenAI,
     pub storage: Storage,
+    cache: EmbeddingCache,
+    pub usage: UsageMeter,
     pub knowledge: Arc<RwLock<Knowledge>>,
     semaphore: Arc<Semaphore>,
 }
 
 impl Brain {
-    pub async fn new(name: String, admin: String) -> Self {
+    pub async fn new(
+        name: String,
+        admin: String,
+        templates: HashMap<Language, PromptTemplate>,
+    ) -> Self {
+        let storage = Storage::new().await;
+        let usage = UsageMeter::new(storage.operator.clone(), &[&CHAT_MODEL, EMBEDDING_MODEL])
+            .await
+            .unwrap();
         let brain = Self {
             _metadata: BrainMetadata { name, admin },
-            openai_client: Client::new(),
-            storage: Storage::new().await,
+            templates,
+            openai: OpenAI::new(),
+            cache: EmbeddingCache::new(storage.operator.clone()),
+            usage,
+            storage,
             knowledge: Arc::new(RwLock::new(Knowledge::default())),
             semaphore: Arc::new(Semaphore::new(1)),
         };
         let vectors = brain.storage.get_vectors().await.unwrap();
         let list = brain.storage.get_list().await.unwrap();
+        let metadata = brain.storage.get_metadata().await.unwrap();
         {
             let mut write = brain.knowledge.write().await;
             write.vectors = vectors;
+            write.metadata = metadata;
             write.list = list.clone();
         }
-        info!("brain init, recover: len: {}, list: {:?}", list.len(), list);
+        info!(
+            "brain {} (admin: {}, prompt: {}) init, recover: len: {}, list: {:?}",
+            brain._metadata.name,
+            brain._metadata.admin,
+            brain.templates[&Language::Zh].name,
+            list.len(),
+            list
+        );
         brain
     }
 
@@ -62,22 +139,20 @@ impl Brain {
         unlearned_knowledge: UnLearnedKnowledge,
     ) -> Result<(), Box<dyn Error>> {
         let file_name = unlearned_knowledge.file_name.clone();
+        let metadata = unlearned_knowledge.metadata.clone();
 
         // get vectors
         let start = Instant::now();
-        let request = CreateEmbeddingRequestArgs::default()
-            .model("text-embedding-ada-002")
-            .input(unlearned_knowledge.clone())
-            .build()?;
-        let vectors = self
-            .openai_client
-            .embeddings()
-            .create(request)
-            .await?
-            .data
-            .into_iter()
-            .map(|e| e.embedding)
+        let texts = unlearned_knowledge
+            .chunks
+            .iter()
+            .map(embedding_text)
             .collect::<Vec<_>>();
+        let ledger = Ledger::default();
+        let vectors = self.embed(texts, &ledger).await;
+        self.commit_usage(UsageKind::Document, &file_name, &ledger)
+            .await;
+        let vectors = vectors?;
         let elapsed = start.elapsed().as_secs_f64();
         info!("embedding {} spends {}s", file_name, elapsed);
 
@@ -93,6 +168,7 @@ impl Brain {
         {
             let mut write = self.knowledge.write().await;
             write.vectors.insert(index, vectors);
+            write.metadata.insert(index, metadata);
             write.list.push(file_name.clone());
         }
         drop(permit);
@@ -100,97 +176,525 @@ impl Brain {
         Ok(())
     }
 
-    pub async fn query(
+    // emits events to tx, returns the answer so that it can be appended to the conversation
+    // stops at the next await point once cancel fires or tx is closed
+    // the answer is in language, or in the language of the query when it is None
+    // only documents whose metadata passes the filter are retrieved
+    pub async fn query<S>(
         &self,
         query: String,
-        tx: &mut SplitSink<WebSocket, Message>,
-    ) -> Result<(), Box<dyn Error>> {
-        // embedding query
+        language: Option<Language>,
+        filter: &Filter,
+        conversation: &Conversation,
+        tx: &mut S,
+        cancel: &CancellationToken,
+    ) -> Result<String>
+    where
+        S: Sink<QueryEvent> + Unpin,
+        S::Error: Error + Send + Sync + 'static,
+    {
+        tx.send(QueryEvent::Start {
+            query: query.clone(),
+        })
+        .await?;
+        let language = language.unwrap_or_else(|| detect(&query));
+        let ledger = Ledger::default();
+        let result = match self.usage.check_budget() {
+            Ok(_) => {
+                self.answer(&query, language, filter, conversation, tx, cancel, &ledger)
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+        self.commit_usage(UsageKind::Query, &query, &ledger).await;
+        match result {
+            Ok(answer) => Ok(answer),
+            Err(e) => {
+                let _ = tx
+                    .send(QueryEvent::Error {
+                        language,
+                        message: e.to_string(),
+                    })
+                    .await;
+                Err(e)
+            }
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn answer<S>(
+        &self,
+        query: &str,
+        language: Language,
+        filter: &Filter,
+        conversation: &Conversation,
+        tx: &mut S,
+        cancel: &CancellationToken,
+        ledger: &Ledger,
+    ) -> Result<String>
+    where
+        S: Sink<QueryEvent> + Unpin,
+        S::Error: Error + Send + Sync + 'static,
+    {
         let start = Instant::now();
-        let request = CreateEmbeddingRequestArgs::default()
-            .model("text-embedding-ada-002")
-            .input(query.clone())
+        // dropping a pending request aborts it
+        let prepared = tokio::select! {
+            prepared = self.prepare(query, language, filter, conversation, ledger) => prepared?,
+            _ = cancel.cancelled() => {
+                info!("query: {} cancelled before generation", query);
+                return Err(anyhow::anyhow!(CANCELLED));
+            }
+        };
+        let retrieval = start.elapsed().as_secs_f64();
+
+        // query openai
+        let generation_start = Instant::now();
+        let request = CreateChatCompletionRequestArgs::default()
+            .max_tokens(ANSWER_TOKENS)
+            .model(CHAT_MODEL.as_str())
+            .temperature(0.0)
+            .messages([
+                ChatCompletionRequestMessageArgs::default()
+                    .role(Role::System)
+                    .content(prepared.prompt.system)
+                    .build()?,
+                ChatCompletionRequestMessageArgs::default()
+                    .role(Role::User)
+                    .content(prepared.prompt.user)
+                    .build()?,
+            ])
             .build()?;
+        let prompt_tokens = self.openai.prompt_tokens(&request);
+        let model = request.model.clone();
+        info!("send query to openai, wait for response...");
+        let mut stream = tokio::select! {
+            stream = self.openai.chat_stream(request) => stream?,
+            _ = cancel.cancelled() => {
+                info!("query: {} cancelled before generation", query);
+                return Err(anyhow::anyhow!(CANCELLED));
+            }
+        };
+
+        debug!("query: {} replying...", query);
+        let mut answer = String::new();
+        let mut cancelled = false;
+        let mut broken = None;
+        while !cancelled {
+            let res = tokio::select! {
+                res = stream.next() => res,
+                _ = cancel.cancelled() => break,
+            };
+            let Some(res) = res else {
+                break;
+            };
+            let response = match res {
+                Ok(response) => response,
+                // a broken stream ends the answer with an error instead of the sources
+                Err(e) => {
+                    broken = Some(e);
+                    break;
+                }
+            };
+            for c in response.choices.iter() {
+                if let Some(content) = &c.delta.content {
+                    answer += content;
+                    // the client is gone
+                    if tx
+                        .send(QueryEvent::Delta {
+                            content: content.clone(),
+                        })
+                        .await
+                        .is_err()
+                    {
+                        cancelled = true;
+                    }
+                }
+            }
+        }
+        // streamed responses carry no usage, so completion tokens are counted locally
+        let completion_tokens = self.openai.count_tokens(&answer);
+        ledger.add(&model, prompt_tokens, completion_tokens, true);
+        if cancelled || cancel.is_cancelled() {
+            // dropping the stream closes the upstream connection
+            drop(stream);
+            info!(
+                "query: {} cancelled during generation, partial usage: prompt_tokens: {}, completion_tokens: {}",
+                query, prompt_tokens, completion_tokens
+            );
+            return Err(anyhow::anyhow!(CANCELLED));
+        }
+        if let Some(e) = broken {
+            warn!(
+                "query: {} stream broken after {} completion tokens: {}",
+                query, completion_tokens, e
+            );
+            return Err(e.context("openai stream broken"));
+        }
+        let apology = match language {
+            Language::Zh => "抱歉",
+            Language::En => "sorry",
+        };
+        let answered = !answer
+            .chars()
+            .take(12)
+            .collect::<String>()
+            .to_lowercase()
+            .contains(apology);
+        let mut sources = prepared.sources;
+        mark_cited(&mut sources, &answer);
+        tx.send(QueryEvent::Sources {
+            answered,
+            language,
+            sources,
+        })
+        .await?;
+        let generation = generation_start.elapsed().as_secs_f64();
+        info!("query openai: {} spends {}s", query, generation);
+
+        let models = ledger.models();
+        let embedding_tokens = models
+            .get(EMBEDDING_MODEL)
+            .map_or(0, |usage| usage.prompt_tokens);
+        tx.send(QueryEvent::Done {
+            usage: Usage {
+                prompt_tokens,
+                completion_tokens,
+                total_tokens: prompt_tokens + completion_tokens,
+                embedding_tokens,
+                cost: self.usage.cost(&models),
+            },
+            timings: Timings {
+                retrieval,
+                generation,
+                total: start.elapsed().as_secs_f64(),
+            },
+        })
+        .await?;
+
+        Ok(answer)
+    }
+
+    // the prompt which would be sent for a fresh question
+    pub async fn dry_run(
+        &self,
+        query: &str,
+        language: Option<Language>,
+        filter: &Filter,
+    ) -> Result<Prepared> {
+        self.usage.check_budget()?;
+        let language = language.unwrap_or_else(|| detect(query));
+        let ledger = Ledger::default();
+        let prepared = self
+            .prepare(query, language, filter, &Conversation::default(), &ledger)
+            .await;
+        self.commit_usage(UsageKind::Query, query, &ledger).await;
+        prepared
+    }
+
+    // embed and match the query, then render the prompt, everything before calling the chat model
+    async fn prepare(
+        &self,
+        query: &str,
+        language: Language,
+        filter: &Filter,
+        conversation: &Conversation,
+        ledger: &Ledger,
+    ) -> Result<Prepared> {
+        let history = conversation.trimmed(&self.openai, *HISTORY_TOKENS, language);
+
+        // a follow-up question is rewritten into a standalone one for retrieval
+        let standalone = if history.is_empty() {
+            query.to_string()
+        } else {
+            match self.rewrite(query, language, &history, ledger).await {
+                Ok(rewritten) => {
+                    info!("rewrite query: {} -> {}", query, rewritten);
+                    rewritten
+                }
+                Err(e) => {
+                    warn!("rewrite query: {} failed: {}", query, e);
+                    query.to_string()
+                }
+            }
+        };
+
+        // expand query
+        let mut variants = vec![standalone.clone()];
+        match expand(
+            &self.openai,
+            ledger,
+            *QUERY_EXPANSION,
+            &standalone,
+            *QUERY_VARIANTS,
+            language,
+        )
+        .await
+        {
+            Ok(expanded) => {
+                debug!("expand query: {} -> {:?}", standalone, expanded);
+                variants.extend(expanded);
+            }
+            Err(e) => warn!("expand query: {} failed: {}", standalone, e),
+        }
+
+        // embedding query
+        let start = Instant::now();
         info!("request embedding, wait for response...");
-        let vector = self.openai_client.embeddings().create(request).await?.data[0]
-            .embedding
-            .clone();
+        let vectors = self.embed(variants.clone(), ledger).await?;
         let elapsed = start.elapsed().as_secs_f64();
-        info!("embedding query: {} spends {}s", query, elapsed);
+        info!("embedding query: {} spends {}s", standalone, elapsed);
 
         // match
         let start = Instant::now();
-        let matched = self.retrieve(&vector, &query).await?;
+        let (matched, mut sources, variant) = self.retrieve(&vectors, &standalone, filter).await?;
+        for source in sources.iter_mut() {
+            source.location = source.location(language);
+        }
+        let variant = variants.swap_remove(variant);
         let _uploader = matched.uploader;
         let file_name = matched.file_name;
-        let context = matched
+        let mut contents = matched
             .chunks
             .iter()
             .map(|c| c.content.clone())
-            .collect::<String>();
-        let page = matched.chunks[0].page;
+            .collect::<Vec<_>>();
+        let page = sources.iter().find(|s| s.matched).map_or(0, |s| s.page);
         let elapsed = start.elapsed().as_secs_f64();
         info!(
-            "match query: {} spends {}s, matched file_name: {}, page: {}",
-            query, elapsed, file_name, page
+            "match query: {} spends {}s, matched file_name: {}, page: {}, by variant: {}",
+            query, elapsed, file_name, page, variant
         );
 
-        // query openai
+        // fit the prompt into the context window, the oldest turns of the history are dropped
+        // first, then neighbours at the ends of the window, the lower scored end first, then the
+        // matched chunk is cut
+        let mut history = history;
+        let template = &self.templates[&language];
+        let budget = context_window(&CHAT_MODEL).saturating_sub(ANSWER_TOKENS as usize);
+        let prompt = loop {
+            let prompt = template.render(&PromptVars {
+                context: &render_passages(&sources, &contents, language),
+                question: query,
+                sources: &render_sources(&sources, language),
+                history: &render_history(&history, language),
+            });
+            let tokens = self
+                .openai
+                .messages_tokens([prompt.system.as_str(), prompt.user.as_str()].into_iter());
+            if tokens <= budget {
+                break prompt;
+            }
+            if !history.is_empty() {
+                history.remove(0);
+                info!(
+                    "prompt of query: {} has {} tokens over budget {}, drop the oldest turn, {} left",
+                    query,
+                    tokens,
+                    budget,
+                    history.len()
+                );
+                continue;
+            }
+            if sources.len() > 1 {
+                let last = sources.len() - 1;
+                let i = if sources[0].matched {
+                    last
+                } else if sources[last].matched || sources[0].score < sources[last].score {
+                    0
+                } else {
+                    last
+                };
+                let dropped = sources.remove(i);
+                contents.remove(i);
+                for (id, source) in sources.iter_mut().enumerate() {
+                    source.id = id + 1;
+                }
+                info!(
+                    "prompt of query: {} has {} tokens over budget {}, drop chunk {} of {}, score: {}",
+                    query, tokens, budget, dropped.chunk, dropped.file_name, dropped.score
+                );
+                continue;
+            }
+            let over = tokens - budget;
+            let content_tokens = self.openai.count_tokens(&contents[0]);
+            if over >= content_tokens {
+                return Err(anyhow::anyhow!(
+                    "prompt of query: {} has {} tokens over budget {} without context",
+                    query,
+                    tokens,
+                    budget
+                ));
+            }
+            let chars = contents[0].chars().count();
+            let keep = chars * (content_tokens - over) / content_tokens;
+            contents[0] = contents[0].chars().take(keep).collect();
+            info!(
+                "prompt of query: {} has {} tokens over budget {}, cut matched chunk to {} of {} chars",
+                query, tokens, budget, keep, chars
+            );
+        };
+        Ok(Prepared {
+            prompt,
+            language,
+            sources,
+            variant,
+        })
+    }
+
+    // retrieval only, the chat model is not involved
+    // only documents whose metadata passes the filter are searched
+    pub async fn search(
+        &self,
+        query: &str,
+        top_k: usize,
+        filter: &Filter,
+    ) -> Result<Vec<SearchHit>> {
+        self.usage.check_budget()?;
         let start = Instant::now();
+        let ledger = Ledger::default();
+        let vector = self.embed(vec![query.to_string()], &ledger).await;
+        self.commit_usage(UsageKind::Query, query, &ledger).await;
+        let vector = vector?.remove(0);
+        let top_n = {
+            let read = self.knowledge.read().await;
+            let empty = BTreeMap::new();
+            match_top_n(&read.vectors, &vector, 0, |index| {
+                filter.is_empty() || filter.matches(read.metadata.get(&index).unwrap_or(&empty))
+            })
+        };
+        let ranked = match_ranked(top_n, query, self.storage.operator.clone()).await?;
+
+        let mut hits = vec![];
+        for matched in ranked.into_iter().take(top_k) {
+            let start_index = matched.vector_index - (*CHUNK_HEAD).min(matched.vector_index);
+            let end_index = (matched.vector_index + *CHUNK_TAIL + 1).min(matched.len);
+            let loaded = self
+                .storage
+                .load(matched.index, (start_index..end_index).collect())
+                .await?;
+            let relative_index = matched.vector_index - start_index;
+            let chunk = &loaded.chunks[relative_index];
+            hits.push(SearchHit {
+                file_name: loaded.file_name.clone(),
+                index: matched.index,
+                chunk: matched.vector_index,
+                section: chunk.section.clone(),
+                metadata: loaded.metadata.clone(),
+                page: chunk.page,
+                unit_kind: loaded.unit_kind,
+                end_page: chunk.end_page,
+                paragraph: chunk.paragraph,
+                end_paragraph: chunk.end_paragraph,
+                char_start: chunk.char_start,
+                char_end: chunk.char_end,
+                score: matched.similarity,
+                cosine: matched.cosine,
+                lexical: matched.lexical,
+                content: chunk.content.clone(),
+                before: loaded.chunks[..relative_index]
+                    .iter()
+                    .map(|c| c.content.clone())
+                    .collect(),
+                after: loaded.chunks[relative_index + 1..]
+                    .iter()
+                    .map(|c| c.content.clone())
+                    .collect(),
+            });
+        }
+        let elapsed = start.elapsed().as_secs_f64();
+        info!(
+            "search: {} spends {}s, hits: {}",
+            query,
+            elapsed,
+            hits.len()
+        );
+        Ok(hits)
+    }
+
+    async fn rewrite(
+        &self,
+        query: &str,
+        language: Language,
+        history: &[Turn],
+        ledger: &Ledger,
+    ) -> Result<String> {
+        let (system, follow_up) = match language {
+            Language::Zh => (REWRITE_PROMPT_ZH, "后续问题：\n"),
+            Language::En => (REWRITE_PROMPT_EN, "Follow-up question:\n"),
+        };
         let request = CreateChatCompletionRequestArgs::default()
-            .max_tokens(1200u16)
-            .model("gpt-3.5-turbo")
+            .max_tokens(200u16)
+            .model(CHAT_MODEL.as_str())
             .temperature(0.0)
             .messages([
                 ChatCompletionRequestMessageArgs::default()
                     .role(Role::System)
-                    .content("你是一名客服经理，请仅根据提供的已知信息回答问题，并不要使用您的先验知识。请详细且礼貌地回答问题。")
+                    .content(system)
                     .build()?,
                 ChatCompletionRequestMessageArgs::default()
                     .role(Role::User)
-                    .content(
-                        "已知信息：\n".to_string() + &context + "\n\n" + "问题：\n" + &query + "？",
-                    )
+                    .content(render_history(history, language) + follow_up + query)
                     .build()?,
             ])
             .build()?;
-        info!("send query to openai, wait for response...");
-        let mut stream = self.openai_client.chat().create_stream(request).await?;
+        let rewritten = self.openai.chat(request, ledger).await?;
+        let rewritten = rewritten.trim();
+        if rewritten.is_empty() {
+            return Err(anyhow::anyhow!("empty rewrite"));
+        }
+        Ok(rewritten.to_string())
+    }
 
-        tx.send(Message::text(":\n")).await?;
-        debug!("query: {} replying...", query);
-        let mut is_failed = String::new();
-        while let Some(res) = stream.next().await {
-            if let Ok(response) = res {
-                for c in response.choices.iter() {
-                    if let Some(content) = &c.delta.content {
-                        let _ = tx.send(Message::text(content)).await;
-                        if is_failed.len() < 18 {
-                            is_failed += content;
-                        }
-                    }
-                }
+    // embed texts in order, only cache misses are sent to openai
+    async fn embed(&self, texts: Vec<String>, ledger: &Ledger) -> Result<Vec<Vec<f32>>> {
+        let mut vectors = self.cache.get(EMBEDDING_MODEL, &texts).await;
+        let misses = texts
+            .iter()
+            .zip(vectors.iter())
+            .filter(|(_, v)| v.is_none())
+            .map(|(t, _)| t.clone())
+            .collect::<Vec<_>>();
+        if !misses.is_empty() {
+            let request = CreateEmbeddingRequestArgs::default()
+                .model(EMBEDDING_MODEL)
+                .input(misses.clone())
+                .build()?;
+            let embedded = self.openai.embeddings(request, ledger).await?;
+            if embedded.len() != misses.len() {
+                return Err(anyhow::anyhow!("embedding count not match"));
+            }
+            if let Err(e) = self.cache.put(EMBEDDING_MODEL, &misses, &embedded).await {
+                warn!("embedding cache write failed: {}", e);
+            }
+            let mut embedded = embedded.into_iter();
+            for v in vectors.iter_mut().filter(|v| v.is_none()) {
+                *v = embedded.next();
             }
         }
-        let addition_info = if is_failed.contains(&"抱歉".to_string()) {
-            "（匹配失败，请检查问题或文档）".to_string()
-        } else if file_name.ends_with(".pdf") {
-            format!("（详见 {} ，第 {} 页）", file_name, page)
-        } else {
-            format!("（详见 {} ，第 {} 段）", file_name, page)
-        };
-        tx.send(Message::text("\n".to_string() + &addition_info))
-            .await?;
-        let elapsed = start.elapsed().as_secs_f64();
-        info!("query openai: {} spends {}s", query, elapsed);
-
-        Ok(())
+        Ok(vectors.into_iter().flatten().collect())
     }
 
-    async fn retrieve(&self, vector: &[f32], query: &str) -> Result<UnLearnedKnowledge> {
+    // returns matched chunks with their sources and the index of the vector which found them
+    async fn retrieve(
+        &self,
+        vectors: &[Vec<f32>],
+        query: &str,
+        filter: &Filter,
+    ) -> Result<(UnLearnedKnowledge, Vec<Source>, usize)> {
         let top_n = {
-            let map = &self.knowledge.read().await.vectors;
-            match_top_n(map, vector)
+            let read = self.knowledge.read().await;
+            let empty = BTreeMap::new();
+            let top_n_list = vectors
+                .iter()
+                .enumerate()
+                .map(|(variant, vector)| {
+                    match_top_n(&read.vectors, vector, variant, |index| {
+                        filter.is_empty()
+                            || filter.matches(read.metadata.get(&index).unwrap_or(&empty))
+                    })
+                })
+                .collect::<Vec<_>>();
+            merge_top_n(top_n_list)
         };
         let matched = match_final(top_n, query, self.storage.operator.clone()).await?;
 
@@ -200,13 +704,63 @@ impl Brain {
         };
         let end_index = (matched.vector_index + *CHUNK_TAIL + 1).min(matched.len);
         let vector_indexs = (start_index..end_index).collect::<Vec<_>>();
+        let unlearned_knowledge = self
+            .storage
+            .load(matched.index, vector_indexs.clone())
+            .await?;
 
-        // index 0 records matched page
-        let matched_relative_index = matched.vector_index - start_index;
-        let mut unlearned_knowledge = self.storage.load(matched.index, vector_indexs).await?;
-        let matched_page = unlearned_knowledge.chunks[matched_relative_index].page;
-        unlearned_knowledge.chunks[0].page = matched_page;
-        Ok(unlearned_knowledge)
+        // in document order, which is the order of passages in the prompt
+        // neighbours are scored by cosine similarity against the vector which found the match
+        let sources = {
+            let map = &self.knowledge.read().await.vectors;
+            let vector = &vectors[matched.variant];
+            vector_indexs
+                .iter()
+                .zip(unlearned_knowledge.chunks.iter())
+                .enumerate()
+                .map(|(i, (j, chunk))| Source {
+                    id: i + 1,
+                    file_name: unlearned_knowledge.file_name.clone(),
+                    index: matched.index,
+                    chunk: *j,
+                    section: chunk.section.clone(),
+                    metadata: unlearned_knowledge.metadata.clone(),
+                    page: chunk.page,
+                    unit_kind: unlearned_knowledge.unit_kind,
+                    end_page: chunk.end_page,
+                    paragraph: chunk.paragraph,
+                    end_paragraph: chunk.end_paragraph,
+                    char_start: chunk.char_start,
+                    char_end: chunk.char_end,
+                    // rendered once the language is known
+                    location: String::new(),
+                    snippet: snippet(&chunk.content),
+                    score: if *j == matched.vector_index {
+                        matched.similarity
+                    } else {
+                        cosine_similarity(vector, &map[&matched.index][*j])
+                    },
+                    matched: *j == matched.vector_index,
+                    cited: false,
+                })
+                .collect::<Vec<_>>()
+        };
+        Ok((unlearned_knowledge, sources, matched.variant))
+    }
+
+    // usage is recorded even when the work failed or was cancelled, the tokens are spent anyway
+    async fn commit_usage(&self, kind: UsageKind, name: &str, ledger: &Ledger) -> f64 {
+        match self.usage.commit(kind, name, ledger).await {
+            Ok(cost) => cost,
+            Err(e) => {
+                warn!("commit usage of {} failed: {}", name, e);
+                0.0
+            }
+        }
+    }
+
+    pub async fn load_chunk(&self, index: usize, chunk: usize) -> Result<UnLearnedKnowledge> {
+        self.storage.load(index, vec![chunk]).await
     }
 
     pub async fn get_list(&self) -> Vec<String> {
@@ -214,3 +768,12 @@ impl Brain {
         read.list.clone()
     }
 }
+
+// the heading path is embedded along with the content, so that a chunk is found by its section
+fn embedding_text(chunk: &UnLearnedChunk) -> String {
+    if chunk.section.is_empty() {
+        chunk.content.clone()
+    } else {
+        chunk.section.clone() + "\n" + &chunk.content
+    }
+}
@@ -0,0 +1,103 @@
+// content-addressed embedding cache, shares the sled of storage
+//
+// cache protocol:
+// cache/next -> total count of inserted entries, next slot is next % capacity
+// cache/slot/[k] -> key of the entry held by slot k
+// cache/[model]/[sha256 of text] -> vector
+//
+// slots form a ring, so when the cache is full the oldest entry is evicted first
+
+use super::storage::{bytes_to_float, float_to_bytes, string_decode, usize_decode};
+use crate::EMBEDDING_CACHE_SIZE;
+use anyhow::Result;
+use opendal::Operator;
+use sha2::{Digest, Sha256};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+#[derive(Clone)]
+pub struct EmbeddingCache {
+    operator: Operator,
+    capacity: usize,
+    hits: Arc<AtomicUsize>,
+    misses: Arc<AtomicUsize>,
+    write_lock: Arc<Mutex<()>>,
+}
+
+impl EmbeddingCache {
+    pub fn new(operator: Operator) -> Self {
+        Self {
+            operator,
+            capacity: *EMBEDDING_CACHE_SIZE,
+            hits: Arc::new(AtomicUsize::new(0)),
+            misses: Arc::new(AtomicUsize::new(0)),
+            write_lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    // returns one slot per text, None on miss
+    pub async fn get(&self, model: &str, texts: &[String]) -> Vec<Option<Vec<f32>>> {
+        let mut vectors = vec![];
+        for text in texts {
+            let vector = if self.capacity == 0 {
+                None
+            } else {
+                self.operator
+                    .read(&key(model, text))
+                    .await
+                    .ok()
+                    .map(|bytes| bytes_to_float(&bytes))
+            };
+            vectors.push(vector);
+        }
+        let hits = vectors.iter().filter(|v| v.is_some()).count();
+        let total_hits = self.hits.fetch_add(hits, Ordering::Relaxed) + hits;
+        let total_misses =
+            self.misses.fetch_add(texts.len() - hits, Ordering::Relaxed) + texts.len() - hits;
+        info!(
+            "embedding cache hit {}/{}, hit rate since start: {:.2}%",
+            hits,
+            texts.len(),
+            100.0 * total_hits as f64 / (total_hits + total_misses).max(1) as f64
+        );
+        vectors
+    }
+
+    pub async fn put(&self, model: &str, texts: &[String], vectors: &[Vec<f32>]) -> Result<()> {
+        if self.capacity == 0 {
+            return Ok(());
+        }
+        let _guard = self.write_lock.lock().await;
+        let mut next = usize_decode(
+            &self
+                .operator
+                .read("cache/next")
+                .await
+                .unwrap_or(0usize.to_be_bytes().to_vec()),
+        );
+        for (text, vector) in texts.iter().zip(vectors.iter()) {
+            let key = key(model, text);
+            if self.operator.is_exist(&key).await? {
+                continue;
+            }
+            let slot = "cache/slot/".to_string() + &(next % self.capacity).to_string();
+            if next >= self.capacity {
+                let evicted = string_decode(&self.operator.read(&slot).await?);
+                self.operator.delete(&evicted).await?;
+                debug!("embedding cache evict {}", evicted);
+            }
+            self.operator.write(&key, float_to_bytes(vector)).await?;
+            self.operator.write(&slot, key).await?;
+            next += 1;
+        }
+        self.operator
+            .write("cache/next", next.to_be_bytes().to_vec())
+            .await?;
+        Ok(())
+    }
+}
+
+fn key(model: &str, text: &str) -> String {
+    format!("cache/{}/{:x}", model, Sha256::digest(text.as_bytes()))
+}
@@ -0,0 +1,146 @@
+// every passage in the prompt is numbered, the model cites them inline as [1], [2],
+// and the markers found in the answer are mapped back to sources
+
+use super::event::Source;
+use super::language::Language;
+
+const SNIPPET_CHARS: usize = 80;
+
+// {context} of the prompt
+pub fn render_passages(sources: &[Source], contents: &[String], language: Language) -> String {
+    sources
+        .iter()
+        .zip(contents.iter())
+        .map(|(source, content)| {
+            format!(
+                "[{}] {}\n{}\n",
+                source.id,
+                source.location(language),
+                content
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// {sources} of the prompt
+pub fn render_sources(sources: &[Source], language: Language) -> String {
+    sources
+        .iter()
+        .map(|source| format!("[{}] {}\n", source.id, source.location(language)))
+        .collect()
+}
+
+pub fn snippet(content: &str) -> String {
+    let content = content.split_whitespace().collect::<Vec<_>>().join(" ");
+    if content.chars().count() > SNIPPET_CHARS {
+        content.chars().take(SNIPPET_CHARS).collect::<String>() + "…"
+    } else {
+        content
+    }
+}
+
+// ids inside [1], [1,2], [1][2] or 【1】, in order of first appearance
+pub fn cited_ids(answer: &str) -> Vec<usize> {
+    let mut ids = vec![];
+    let mut rest = answer;
+    while let Some(start) = rest.find(['[', '【']) {
+        rest = &rest[start..];
+        rest = &rest[rest.chars().next().unwrap().len_utf8()..];
+        let Some(end) = rest.find([']', '】']) else {
+            break;
+        };
+        let inner = &rest[..end];
+        let parsed = inner
+            .split([',', '，', '、'])
+            .map(|id| id.trim().parse::<usize>())
+            .collect::<Result<Vec<_>, _>>();
+        if let Ok(parsed) = parsed {
+            for id in parsed {
+                if !ids.contains(&id) {
+                    ids.push(id);
+                }
+            }
+        }
+    }
+    ids
+}
+
+pub fn mark_cited(sources: &mut [Source], answer: &str) {
+    let ids = cited_ids(answer);
+    for source in sources.iter_mut() {
+        source.cited = ids.contains(&source.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_file::UnitKind;
+    use std::collections::BTreeMap;
+
+    fn source(id: usize) -> Source {
+        Source {
+            id,
+            file_name: "manual.pdf".to_string(),
+            unit_kind: UnitKind::Page,
+            page: id + 1,
+            end_page: id + 1,
+            index: 0,
+            chunk: id,
+            section: String::new(),
+            metadata: BTreeMap::new(),
+            paragraph: 0,
+            end_paragraph: 0,
+            char_start: 0,
+            char_end: 0,
+            location: String::new(),
+            snippet: String::new(),
+            score: 0.0,
+            matched: true,
+            cited: false,
+        }
+    }
+
+    #[test]
+    fn finds_cited_ids() {
+        assert_eq!(cited_ids("a [1] b [3][2] c [1]"), vec![1, 3, 2]);
+        assert_eq!(
+            cited_ids("见【2】和[1, 4]以及[5，6、7]"),
+            vec![2, 1, 4, 5, 6, 7]
+        );
+        assert_eq!(cited_ids("[ 2 ] and [x] and [1a] and [1"), vec![2]);
+        assert!(cited_ids("no citation").is_empty());
+    }
+
+    #[test]
+    fn marks_cited_sources() {
+        let mut sources = (1..=3).map(source).collect::<Vec<_>>();
+        mark_cited(&mut sources, "answer [3] and [9]");
+        assert_eq!(
+            sources.iter().map(|s| s.cited).collect::<Vec<_>>(),
+            vec![false, false, true]
+        );
+    }
+
+    #[test]
+    fn renders_numbered_passages() {
+        let sources = vec![source(1), source(2)];
+        let contents = vec!["first".to_string(), "second".to_string()];
+        assert_eq!(
+            render_passages(&sources, &contents, Language::En),
+            "[1] manual.pdf, page 2\nfirst\n\n[2] manual.pdf, page 3\nsecond\n"
+        );
+        assert_eq!(
+            render_sources(&sources, Language::Zh),
+            "[1] manual.pdf ，第 2 页\n[2] manual.pdf ，第 3 页\n"
+        );
+    }
+
+    #[test]
+    fn snippets_are_cut() {
+        assert_eq!(snippet(" a\n\tb  c "), "a b c");
+        let long = "字".repeat(100);
+        assert_eq!(snippet(&long), "字".repeat(SNIPPET_CHARS) + "…");
+    }
+}
@@ -0,0 +1,72 @@
+// history of one websocket session, kept in memory only
+
+use super::language::Language;
+use super::openai::OpenAI;
+
+// turns older than this are dropped even if they would fit the token budget
+const MAX_TURNS: usize = 20;
+
+#[derive(Clone, Debug)]
+pub struct Turn {
+    pub question: String,
+    pub answer: String,
+}
+
+#[derive(Clone, Default)]
+pub struct Conversation {
+    turns: Vec<Turn>,
+}
+
+impl Conversation {
+    pub fn push(&mut self, question: String, answer: String) {
+        self.turns.push(Turn { question, answer });
+        if self.turns.len() > MAX_TURNS {
+            self.turns.remove(0);
+        }
+    }
+
+    // latest turns whose rendered text fits in budget tokens, oldest first
+    pub fn trimmed(&self, openai: &OpenAI, budget: usize, language: Language) -> Vec<Turn> {
+        let mut used = 0;
+        let mut turns = vec![];
+        for turn in self.turns.iter().rev() {
+            used += openai.count_tokens(&render_turn(turn, language));
+            if used > budget {
+                break;
+            }
+            turns.push(turn.clone());
+        }
+        if turns.len() < self.turns.len() {
+            debug!(
+                "conversation history trimmed: {} of {} turns kept",
+                turns.len(),
+                self.turns.len()
+            );
+        }
+        turns.reverse();
+        turns
+    }
+}
+
+pub fn render_history(turns: &[Turn], language: Language) -> String {
+    if turns.is_empty() {
+        return String::new();
+    }
+    let title = match language {
+        Language::Zh => "对话历史：\n",
+        Language::En => "Conversation history:\n",
+    };
+    title.to_string()
+        + &turns
+            .iter()
+            .map(|turn| render_turn(turn, language))
+            .collect::<String>()
+        + "\n"
+}
+
+fn render_turn(turn: &Turn, language: Language) -> String {
+    match language {
+        Language::Zh => format!("问：{}\n答：{}\n", turn.question, turn.answer),
+        Language::En => format!("Q: {}\nA: {}\n", turn.question, turn.answer),
+    }
+}
@@ -0,0 +1,270 @@
+// events emitted by Brain::query, encoded by the transport
+//
+// json protocol, one message per event, every message carries the protocol version:
+// {"v":1,"type":"start","query":"..."}
+// {"v":1,"type":"delta","content":"..."}
+// {"v":1,"type":"sources","answered":true,"language":"zh","sources":[{"id":1,"file_name":"...","index":0,"chunk":3,"section":"安装 > 配置","unit_kind":"page","page":2,"end_page":3,"paragraph":5,"end_paragraph":2,"char_start":1024,"char_end":1530,"location":"... ，安装 > 配置 ，第 2-3 页","snippet":"...","score":0.87,"matched":true,"cited":true}]}
+// {"v":1,"type":"error","language":"zh","message":"..."}
+// {"v":1,"type":"done","usage":{...},"timings":{...}}
+//
+// text protocol: ":\n", raw deltas, then a citation sentence or an error sentence
+//
+// sse (/api/query/stream): the json messages above as data, with the type as event name
+//
+// client messages are {"type":"query","query":"...","language":"en","filter":{...}} and
+// {"type":"cancel"}, language is optional and detected from the query when missing, filter is
+// optional and the same as the filter of /api/search, any other text is taken as a query
+
+use super::filter::Filter;
+use super::language::Language;
+use crate::chunk_file::UnitKind;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+pub const PROTOCOL_VERSION: u32 = 1;
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum QueryEvent {
+    Start {
+        query: String,
+    },
+    Delta {
+        content: String,
+    },
+    Sources {
+        // false when the model says it can not answer from the sources
+        answered: bool,
+        // language of the answer
+        language: Language,
+        sources: Vec<Source>,
+    },
+    Error {
+        // language of the query
+        language: Language,
+        message: String,
+    },
+    Done {
+        usage: Usage,
+        timings: Timings,
+    },
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Source {
+    // citation number in the prompt, starts from 1
+    pub id: usize,
+    pub file_name: String,
+    // knowledge index in storage
+    pub index: usize,
+    // chunk index inside the knowledge
+    pub chunk: usize,
+    // heading path, empty for files without headings
+    pub section: String,
+    // document metadata, e.g. title, url
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
+    pub unit_kind: UnitKind,
+    pub page: usize,
+    pub end_page: usize,
+    pub paragraph: usize,
+    pub end_paragraph: usize,
+    // offsets in the extracted text of the file
+    pub char_start: usize,
+    pub char_end: usize,
+    // file name, section and page, slide or record in the language of the answer
+    pub location: String,
+    pub snippet: String,
+    pub score: f32,
+    // the chunk found by retrieval, others are its neighbours
+    pub matched: bool,
+    // referenced in the answer as [id]
+    pub cited: bool,
+}
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct Usage {
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+    pub total_tokens: usize,
+    // tokens of the query embeddings missing from the cache
+    pub embedding_tokens: usize,
+    // USD of the whole query, by the price table
+    pub cost: f64,
+}
+
+// seconds
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct Timings {
+    pub retrieval: f64,
+    pub generation: f64,
+    pub total: f64,
+}
+
+// complete answer of the http api, folded from the events
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct Answer {
+    pub v: u32,
+    pub answer: String,
+    pub answered: bool,
+    pub language: Language,
+    pub sources: Vec<Source>,
+    pub usage: Usage,
+    pub timings: Timings,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Answer {
+    pub fn push(&mut self, event: QueryEvent) {
+        self.v = PROTOCOL_VERSION;
+        match event {
+            QueryEvent::Start { .. } => {}
+            QueryEvent::Delta { content } => self.answer += &content,
+            QueryEvent::Sources {
+                answered,
+                language,
+                sources,
+            } => {
+                self.answered = answered;
+                self.language = language;
+                self.sources = sources;
+            }
+            QueryEvent::Error { message, .. } => self.error = Some(message),
+            QueryEvent::Done { usage, timings } => {
+                self.usage = usage;
+                self.timings = timings;
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    Query {
+        query: String,
+        language: Option<Language>,
+        #[serde(default)]
+        filter: Filter,
+    },
+    // stop the answer in flight
+    Cancel,
+}
+
+impl ClientMessage {
+    pub fn parse(text: &str) -> Self {
+        serde_json::from_str(text).unwrap_or(ClientMessage::Query {
+            query: text.to_string(),
+            language: None,
+            filter: Filter::default(),
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct Envelope<'a> {
+    v: u32,
+    #[serde(flatten)]
+    event: &'a QueryEvent,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Protocol {
+    Json,
+    Text,
+}
+
+impl Source {
+    pub fn location(&self, language: Language) -> String {
+        let range = |start: usize, end: usize| {
+            if end > start {
+                format!("{}-{}", start, end)
+            } else {
+                start.to_string()
+            }
+        };
+        let file_name = match (self.section.is_empty(), language) {
+            (true, _) => self.file_name.clone(),
+            (false, Language::Zh) => format!("{} ，{}", self.file_name, self.section),
+            (false, Language::En) => format!("{}, {}", self.file_name, self.section),
+        };
+        let pages = range(self.page, self.end_page);
+        // records of a sheet are lines of its unit
+        let records = range(self.paragraph, self.end_paragraph);
+        match (language, self.unit_kind) {
+            (Language::Zh, UnitKind::Page) => format!("{} ，第 {} 页", file_name, pages),
+            (Language::Zh, UnitKind::Paragraph) => format!("{} ，第 {} 段", file_name, pages),
+            (Language::Zh, UnitKind::Sheet) => format!("{} ，第 {} 条记录", file_name, records),
+            (Language::Zh, UnitKind::Slide) => format!("{} ，第 {} 张幻灯片", file_name, pages),
+            (Language::En, UnitKind::Page) => format!("{}, page {}", file_name, pages),
+            (Language::En, UnitKind::Paragraph) => format!("{}, paragraph {}", file_name, pages),
+            (Language::En, UnitKind::Sheet) => format!("{}, record {}", file_name, records),
+            (Language::En, UnitKind::Slide) => format!("{}, slide {}", file_name, pages),
+        }
+    }
+}
+
+impl QueryEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            QueryEvent::Start { .. } => "start",
+            QueryEvent::Delta { .. } => "delta",
+            QueryEvent::Sources { .. } => "sources",
+            QueryEvent::Error { .. } => "error",
+            QueryEvent::Done { .. } => "done",
+        }
+    }
+
+    // None means the event is not part of the protocol
+    pub fn encode(&self, protocol: Protocol) -> Option<String> {
+        match protocol {
+            Protocol::Json => serde_json::to_string(&Envelope {
+                v: PROTOCOL_VERSION,
+                event: self,
+            })
+            .ok(),
+            Protocol::Text => match self {
+                QueryEvent::Start { .. } => Some(":\n".to_string()),
+                QueryEvent::Delta { content } => Some(content.clone()),
+                QueryEvent::Sources {
+                    answered,
+                    language,
+                    sources,
+                } => {
+                    let mut cited = sources.iter().filter(|s| s.cited).collect::<Vec<_>>();
+                    if cited.is_empty() {
+                        cited = sources.iter().filter(|s| s.matched).collect();
+                    }
+                    let addition_info = if *answered && !cited.is_empty() {
+                        let locations = cited
+                            .iter()
+                            .map(|s| format!("[{}] {}", s.id, s.location(*language)));
+                        match language {
+                            Language::Zh => {
+                                format!("（详见 {}）", locations.collect::<Vec<_>>().join("；"))
+                            }
+                            Language::En => {
+                                format!("(See {})", locations.collect::<Vec<_>>().join("; "))
+                            }
+                        }
+                    } else {
+                        match language {
+                            Language::Zh => "（匹配失败，请检查问题或文档）".to_string(),
+                            Language::En => {
+                                "(No match found, please check the question or the documents)"
+                                    .to_string()
+                            }
+                        }
+                    };
+                    Some("\n".to_string() + &addition_info)
+                }
+                QueryEvent::Error { language, message } => Some(match language {
+                    Language::Zh => format!("\n（出错：{}）", message),
+                    Language::En => format!("\n(Error: {})", message),
+                }),
+                QueryEvent::Done { .. } => None,
+            },
+        }
+    }
+}
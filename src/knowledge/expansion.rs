@@ -0,0 +1,98 @@
+// optional pre-retrieval step, asks the chat model for more texts to embed besides the query
+//
+// paraphrase -> N questions with the same meaning but different wording
+// hyde -> a hypothetical answer, which usually embeds closer to the document than the question
+
+use super::language::Language;
+use super::openai::OpenAI;
+use super::usage::Ledger;
+use crate::CHAT_MODEL;
+use anyhow::Result;
+use async_openai::types::{
+    ChatCompletionRequestMessageArgs, CreateChatCompletionRequestArgs, Role,
+};
+use std::str::FromStr;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Expansion {
+    Off,
+    Paraphrase,
+    Hyde,
+}
+
+impl FromStr for Expansion {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "off" => Ok(Expansion::Off),
+            "paraphrase" => Ok(Expansion::Paraphrase),
+            "hyde" => Ok(Expansion::Hyde),
+            _ => Err(anyhow::anyhow!("unknown query expansion: {}", s)),
+        }
+    }
+}
+
+// variants besides the query itself, at most n
+pub async fn expand(
+    openai: &OpenAI,
+    ledger: &Ledger,
+    expansion: Expansion,
+    query: &str,
+    n: usize,
+    language: Language,
+) -> Result<Vec<String>> {
+    let system = match (expansion, language) {
+        (Expansion::Off, _) => return Ok(vec![]),
+        (Expansion::Paraphrase, Language::Zh) => format!(
+            "请将用户的问题改写为 {} 个表述不同但含义相同的问题，每行一个，不要编号，不要输出其他内容。",
+            n
+        ),
+        (Expansion::Paraphrase, Language::En) => format!(
+            "Rewrite the user's question into {} questions with the same meaning but different wording, one per line, without numbering or anything else.",
+            n
+        ),
+        (Expansion::Hyde, Language::Zh) => {
+            "请针对用户的问题写一段可能出现在文档中的回答，约100字，不要输出其他内容。".to_string()
+        }
+        (Expansion::Hyde, Language::En) => {
+            "Write a passage of about 80 words that could appear in a document answering the user's question, and nothing else.".to_string()
+        }
+    };
+    let request = CreateChatCompletionRequestArgs::default()
+        .max_tokens(400u16)
+        .model(CHAT_MODEL.as_str())
+        .temperature(0.7)
+        .messages([
+            ChatCompletionRequestMessageArgs::default()
+                .role(Role::System)
+                .content(system)
+                .build()?,
+            ChatCompletionRequestMessageArgs::default()
+                .role(Role::User)
+                .content(query)
+                .build()?,
+        ])
+        .build()?;
+    let content = openai.chat(request, ledger).await?;
+
+    let variants = match expansion {
+        Expansion::Hyde => vec![content.trim().to_string()],
+        _ => content
+            .lines()
+            .map(|l| {
+                l.trim()
+                    .trim_start_matches(|c: char| {
+                        c.is_ascii_digit() || matches!(c, '.' | '、' | ')' | '-' | '*' | ' ')
+                    })
+                    .trim()
+                    .to_string()
+            })
+            .collect(),
+    };
+    Ok(variants
+        .into_iter()
+        .filter(|v| !v.is_empty() && v != query)
+        .take(n)
+        .collect())
+}
@@ -0,0 +1,102 @@
+// conditions on the metadata of documents, all of them must hold, e.g. for emails
+// {"metadata": {"from": "alice@example.com"}, "since": "2024-01-01", "until": "2024-03-31"}
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct Filter {
+    // the value of each key contains the text, case insensitive
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
+    // bounds of the "date" metadata, inclusive, compared as text like 2024-01-31T08:00:00Z so
+    // that a prefix such as 2024-01 works too
+    pub since: Option<String>,
+    pub until: Option<String>,
+}
+
+impl Filter {
+    pub fn is_empty(&self) -> bool {
+        self.metadata.is_empty() && self.since.is_none() && self.until.is_none()
+    }
+
+    // a document without dates fails any date bound, a mbox spans date..=date_end
+    pub fn matches(&self, metadata: &BTreeMap<String, String>) -> bool {
+        let contains = self.metadata.iter().all(|(key, text)| {
+            metadata
+                .get(key)
+                .is_some_and(|value| value.to_lowercase().contains(&text.to_lowercase()))
+        });
+        let date = metadata.get("date");
+        let date_end = metadata.get("date_end").or(date);
+        let since = self
+            .since
+            .as_ref()
+            .is_none_or(|since| date_end.is_some_and(|end| end.as_str() >= since.as_str()));
+        let until = self.until.as_ref().is_none_or(|until| {
+            // the date cut to the length of the bound, so that 2024-01 includes the whole month
+            date.is_some_and(|date| {
+                date.chars()
+                    .take(until.chars().count())
+                    .collect::<String>()
+                    .as_str()
+                    <= until.as_str()
+            })
+        });
+        contains && since && until
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn filter(json: &str) -> Filter {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn matches_metadata_case_insensitive() {
+        let email = metadata(&[("from", "Alice <Alice@Example.com>")]);
+        assert!(filter(r#"{"metadata":{"from":"alice@example"}}"#).matches(&email));
+        assert!(!filter(r#"{"metadata":{"from":"bob"}}"#).matches(&email));
+        assert!(!filter(r#"{"metadata":{"to":"alice"}}"#).matches(&email));
+        assert!(filter("{}").is_empty());
+        assert!(filter("{}").matches(&email));
+    }
+
+    #[test]
+    fn matches_date_bounds() {
+        let email = metadata(&[("date", "2024-03-15T08:00:00Z")]);
+        assert!(filter(r#"{"since":"2024-03-01","until":"2024-03-31"}"#).matches(&email));
+        assert!(filter(r#"{"since":"2024-03","until":"2024-03"}"#).matches(&email));
+        assert!(!filter(r#"{"since":"2024-04"}"#).matches(&email));
+        assert!(!filter(r#"{"until":"2024-02"}"#).matches(&email));
+        assert!(!filter(r#"{"since":"2024"}"#).matches(&BTreeMap::new()));
+    }
+
+    #[test]
+    fn matches_mbox_span() {
+        let mbox = metadata(&[
+            ("date", "2024-01-10T00:00:00Z"),
+            ("date_end", "2024-05-10T00:00:00Z"),
+        ]);
+        assert!(filter(r#"{"since":"2024-04"}"#).matches(&mbox));
+        assert!(filter(r#"{"until":"2024-01-31"}"#).matches(&mbox));
+        assert!(!filter(r#"{"since":"2024-06"}"#).matches(&mbox));
+    }
+
+    #[test]
+    fn compares_non_ascii_dates_by_chars() {
+        let document = metadata(&[("date", "2024年3月1日")]);
+        assert!(!filter(r#"{"until":"2024-3"}"#).matches(&document));
+        assert!(filter(r#"{"until":"2024年4"}"#).matches(&document));
+    }
+}
@@ -12,18 +12,25 @@ pub struct Matched {
     pub index: usize,
     pub len: usize,
     pub vector_index: usize,
-    similarity: f32,
+    // which embedded text found this chunk, 0 is the query itself
+    pub variant: usize,
+    // final score, cosine before match_final and cosine + lexical * factor after
+    pub similarity: f32,
+    pub cosine: f32,
+    pub lexical: f32,
 }
 
 impl PartialOrd for Matched {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.similarity.partial_cmp(&other.similarity)
+        Some(self.cmp(other))
     }
 }
 
 impl Ord for Matched {
     fn cmp(&self, other: &Self) -> Ordering {
-        self.partial_cmp(other).unwrap_or(Ordering::Equal)
+        self.similarity
+            .partial_cmp(&other.similarity)
+            .unwrap_or(Ordering::Equal)
     }
 }
 
@@ -35,12 +42,22 @@ impl PartialEq for Matched {
 
 impl Eq for Matched {}
 
-pub fn match_top_n(map: &HashMap<usize, Vec<Vec<f32>>>, vector: &[f32]) -> Vec<Matched> {
+// only knowledges whose index is kept are searched
+pub fn match_top_n(
+    map: &HashMap<usize, Vec<Vec<f32>>>,
+    vector: &[f32],
+    variant: usize,
+    keep: impl Fn(usize) -> bool,
+) -> Vec<Matched> {
     let mut top_n = Vec::new();
-    let total_len: usize = map.values().map(|v| v.len()).sum();
+    let total_len: usize = map
+        .iter()
+        .filter(|(index, _)| keep(**index))
+        .map(|(_, v)| v.len())
+        .sum();
     let n = (total_len / 6).max(1);
 
-    for (index, vec_list) in map.iter() {
+    for (index, vec_list) in map.iter().filter(|(index, _)| keep(**index)) {
         for (vector_index, vec) in vec_list.iter().enumerate() {
             let similarity = cosine_similarity(vector, vec);
             debug!(
@@ -50,7 +67,10 @@ pub fn match_top_n(map: &HashMap<usize, Vec<Vec<f32>>>, vector: &[f32]) -> Vec<M
             let matched = Matched {
                 index: *index,
                 vector_index,
+                variant,
                 similarity,
+                cosine: similarity,
+                lexical: 0.0,
                 len: vec_list.len(),
             };
 
@@ -71,7 +91,30 @@ pub fn match_top_n(map: &HashMap<usize, Vec<Vec<f32>>>, vector: &[f32]) -> Vec<M
     top_n
 }
 
-pub async fn match_final(top_n: Vec<Matched>, query: &str, operator: Operator) -> Result<Matched> {
+// union of the candidates of every variant, a chunk found twice keeps its best similarity
+pub fn merge_top_n(top_n_list: Vec<Vec<Matched>>) -> Vec<Matched> {
+    let mut merged: HashMap<(usize, usize), Matched> = HashMap::new();
+    for matched in top_n_list.into_iter().flatten() {
+        let key = (matched.index, matched.vector_index);
+        match merged.get(&key) {
+            Some(old) if old.similarity >= matched.similarity => {}
+            _ => {
+                merged.insert(key, matched);
+            }
+        }
+    }
+    let mut merged = merged.into_values().collect::<Vec<_>>();
+    merged.sort_unstable_by(|a, b| b.cmp(a));
+    debug!("merged top_n: {:?}", merged);
+    merged
+}
+
+// re-rank candidates by cosine and text similarity, best first
+pub async fn match_ranked(
+    top_n: Vec<Matched>,
+    query: &str,
+    operator: Operator,
+) -> Result<Vec<Matched>> {
     let mut new_top_n = Vec::new();
     for mut matched in top_n {
         let content = string_decode(
@@ -85,12 +128,13 @@ pub async fn match_final(top_n: Vec<Matched>, query: &str, operator: Operator) -
                 .await?,
         );
         let text_similarity = text_similarity(query, &content);
-        matched.similarity += text_similarity * TEXT_SIMILARITY_FACTOR;
+        matched.lexical = text_similarity;
+        matched.similarity = matched.cosine + text_similarity * TEXT_SIMILARITY_FACTOR;
         debug!(
             "index: {}, vector_index: {}, cosine_similarity: {}, text_similarity: {} * {}, final_similarity: {}",
             matched.index,
             matched.vector_index,
-            matched.similarity - text_similarity * TEXT_SIMILARITY_FACTOR,
+            matched.cosine,
             text_similarity,
             TEXT_SIMILARITY_FACTOR,
             matched.similarity
@@ -98,9 +142,18 @@ pub async fn match_final(top_n: Vec<Matched>, query: &str, operator: Operator) -
         new_top_n.push(matched);
     }
     new_top_n.sort_unstable_by(|a, b| b.cmp(a));
-    debug!("final matched: {:?}", new_top_n[0]);
 
-    Ok(new_top_n[0])
+    Ok(new_top_n)
+}
+
+pub async fn match_final(top_n: Vec<Matched>, query: &str, operator: Operator) -> Result<Matched> {
+    let ranked = match_ranked(top_n, query, operator).await?;
+    let Some(matched) = ranked.first() else {
+        return Err(anyhow::anyhow!("knowledge is empty"));
+    };
+    debug!("final matched: {:?}", matched);
+
+    Ok(*matched)
 }
 
 fn jaro_similarity_without_search_range(a: &str, b: &str) -> f64 {
@@ -123,7 +176,7 @@ fn jaro_similarity_without_search_range(a: &str, b: &str) -> f64 {
     let mut transpositions = 0.0;
     let mut b_match_index = 0;
 
-    for (_, a_elem) in a_chars.enumerate() {
+    for a_elem in a_chars {
         for (j, b_elem) in b_chars.clone().enumerate() {
             if a_elem == b_elem && !b_consumed[j] {
                 b_consumed[j] = true;
@@ -162,7 +215,7 @@ fn magnitude(a: &[f32]) -> f32 {
     a.iter().map(|x| x * x).sum::<f32>().sqrt()
 }
 
-fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
     let dot = dot_product(a, b);
     let mag_a = magnitude(a);
     let mag_b = magnitude(b);
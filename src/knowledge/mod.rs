@@ -1,3 +1,13 @@
 pub mod brain;
+mod cache;
+mod citation;
+pub mod conversation;
+pub mod event;
+pub mod expansion;
+pub mod filter;
+pub mod language;
 mod matching;
+mod openai;
+pub mod prompt;
 mod storage;
+pub mod usage;
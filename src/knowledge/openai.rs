@@ -0,0 +1,567 @@
+// thin OpenAI http client used by brain
+//
+// async-openai only retries 429 and hides the response headers, so requests are sent with reqwest
+// directly while the request/response types are still taken from async-openai.
+//
+// - transient failures (429 except insufficient_quota, 5xx, timeout, connect error) are retried
+//   with exponential backoff and full jitter, `retry-after-ms` / `retry-after` headers are honored
+// - every attempt passes a client-side limiter (requests and tokens per minute) first
+// - streams are only retried before the first byte, a broken stream is returned as error item
+// - there is no deadline of a whole request, only for the response to start and for every chunk
+//   of a stream to arrive, so a long answer is not cut off
+// - usage of every response is added to the caller's ledger, streams carry no usage and are
+//   counted by the caller
+
+use super::usage::Ledger;
+use crate::{OPENAI_API_BASE, OPENAI_MAX_RETRIES, OPENAI_RPM, OPENAI_TIMEOUT, OPENAI_TPM};
+use anyhow::Result;
+use async_openai::types::{
+    CreateChatCompletionRequest, CreateChatCompletionResponse, CreateChatCompletionStreamResponse,
+    CreateEmbeddingRequest, CreateEmbeddingResponse,
+};
+use futures::{Stream, StreamExt};
+use rand::Rng;
+use reqwest::{header::HeaderMap, Response, StatusCode};
+use serde::Serialize;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tiktoken_rs::{cl100k_base, CoreBPE};
+use tokio::sync::Mutex;
+
+const BACKOFF_BASE_MS: u64 = 500;
+const BACKOFF_MAX_MS: u64 = 60_000;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+// every chat message is wrapped with a few tokens of its own, and the reply is primed with 3
+const MESSAGE_TOKENS: usize = 4;
+const REPLY_TOKENS: usize = 3;
+
+pub type ChatStream =
+    Pin<Box<dyn Stream<Item = Result<CreateChatCompletionStreamResponse>> + Send>>;
+
+#[derive(Clone)]
+pub struct OpenAI {
+    http_client: reqwest::Client,
+    api_base: String,
+    api_key: String,
+    pub bpe: Arc<CoreBPE>,
+    limiter: Arc<RateLimiter>,
+    // for the response headers, a json body, and each chunk of a stream
+    timeout: Duration,
+}
+
+impl OpenAI {
+    pub fn new() -> Self {
+        Self::with_api_base(&OPENAI_API_BASE, Duration::from_secs(*OPENAI_TIMEOUT))
+    }
+
+    fn with_api_base(api_base: &str, timeout: Duration) -> Self {
+        let http_client = reqwest::Client::builder()
+            .connect_timeout(CONNECT_TIMEOUT)
+            .build()
+            .unwrap();
+        Self {
+            http_client,
+            api_base: api_base.trim_end_matches('/').to_string(),
+            api_key: std::env::var("OPENAI_API_KEY").unwrap_or_default(),
+            bpe: Arc::new(cl100k_base().unwrap()),
+            limiter: Arc::new(RateLimiter::new(*OPENAI_RPM, *OPENAI_TPM)),
+            timeout,
+        }
+    }
+
+    pub fn count_tokens(&self, text: &str) -> usize {
+        self.bpe.encode_with_special_tokens(text).len()
+    }
+
+    pub async fn embeddings(
+        &self,
+        request: CreateEmbeddingRequest,
+        ledger: &Ledger,
+    ) -> Result<Vec<Vec<f32>>> {
+        let tokens = match &request.input {
+            async_openai::types::EmbeddingInput::String(s) => self.count_tokens(s),
+            async_openai::types::EmbeddingInput::StringArray(v) => {
+                v.iter().map(|s| self.count_tokens(s)).sum()
+            }
+            _ => 0,
+        };
+        let response = self.post("/embeddings", &request, tokens).await?;
+        let mut response = self.json::<CreateEmbeddingResponse>(response).await?;
+        ledger.add(
+            &request.model,
+            response.usage.prompt_tokens as usize,
+            0,
+            false,
+        );
+        response.data.sort_by_key(|e| e.index);
+        Ok(response.data.into_iter().map(|e| e.embedding).collect())
+    }
+
+    pub async fn chat(
+        &self,
+        request: CreateChatCompletionRequest,
+        ledger: &Ledger,
+    ) -> Result<String> {
+        let tokens = self.request_tokens(&request);
+        let response = self.post("/chat/completions", &request, tokens).await?;
+        let response = self.json::<CreateChatCompletionResponse>(response).await?;
+        match &response.usage {
+            Some(usage) => ledger.add(
+                &request.model,
+                usage.prompt_tokens as usize,
+                usage.completion_tokens as usize,
+                false,
+            ),
+            None => ledger.add(
+                &request.model,
+                self.prompt_tokens(&request),
+                response
+                    .choices
+                    .iter()
+                    .map(|c| self.count_tokens(&c.message.content))
+                    .sum(),
+                true,
+            ),
+        }
+        response
+            .choices
+            .into_iter()
+            .next()
+            .map(|c| c.message.content)
+            .ok_or(anyhow::anyhow!("chat completion returns no choice"))
+    }
+
+    pub async fn chat_stream(
+        &self,
+        mut request: CreateChatCompletionRequest,
+    ) -> Result<ChatStream> {
+        request.stream = Some(true);
+        let tokens = self.request_tokens(&request);
+        let response = self.post("/chat/completions", &request, tokens).await?;
+        Ok(sse_stream(response, self.timeout))
+    }
+
+    pub fn prompt_tokens(&self, request: &CreateChatCompletionRequest) -> usize {
+        self.messages_tokens(request.messages.iter().map(|m| m.content.as_str()))
+    }
+
+    pub fn messages_tokens<'a>(&self, messages: impl Iterator<Item = &'a str>) -> usize {
+        messages
+            .map(|m| self.count_tokens(m) + MESSAGE_TOKENS)
+            .sum::<usize>()
+            + REPLY_TOKENS
+    }
+
+    fn request_tokens(&self, request: &CreateChatCompletionRequest) -> usize {
+        self.prompt_tokens(request) + request.max_tokens.unwrap_or_default() as usize
+    }
+
+    async fn post<I: Serialize>(&self, path: &str, body: &I, tokens: usize) -> Result<Response> {
+        let url = self.api_base.clone() + path;
+        let mut attempt = 0;
+        loop {
+            self.limiter.acquire(tokens).await;
+            let request = self
+                .http_client
+                .post(&url)
+                .bearer_auth(&self.api_key)
+                .json(body)
+                .send();
+            let Ok(result) = tokio::time::timeout(self.timeout, request).await else {
+                let err = anyhow::anyhow!(
+                    "openai {} does not respond in {}s",
+                    path,
+                    self.timeout.as_secs_f64()
+                );
+                attempt = self.retry(path, err, None, attempt).await?;
+                continue;
+            };
+            let (err, retry_after) = match result {
+                Ok(response) if response.status().is_success() => return Ok(response),
+                Ok(response) => {
+                    let status = response.status();
+                    let retry_after = retry_after(response.headers());
+                    let text = response.text().await.unwrap_or_default();
+                    let err = anyhow::anyhow!("openai {} returns {}: {}", path, status, text);
+                    if !is_transient(status, &text) {
+                        return Err(err);
+                    }
+                    (err, retry_after)
+                }
+                Err(e) if e.is_timeout() || e.is_connect() => (e.into(), None),
+                Err(e) => return Err(e.into()),
+            };
+            attempt = self.retry(path, err, retry_after, attempt).await?;
+        }
+    }
+
+    // waits before the next attempt, or gives up with err
+    async fn retry(
+        &self,
+        path: &str,
+        err: anyhow::Error,
+        retry_after: Option<Duration>,
+        attempt: usize,
+    ) -> Result<usize> {
+        if attempt >= *OPENAI_MAX_RETRIES {
+            return Err(err.context(format!("give up after {} retries", attempt)));
+        }
+        let delay = backoff(attempt, retry_after);
+        warn!(
+            "openai {} failed: {}, retry {} in {}ms",
+            path,
+            err,
+            attempt + 1,
+            delay.as_millis()
+        );
+        tokio::time::sleep(delay).await;
+        Ok(attempt + 1)
+    }
+
+    async fn json<T: serde::de::DeserializeOwned>(&self, response: Response) -> Result<T> {
+        tokio::time::timeout(self.timeout, response.json::<T>())
+            .await
+            .map_err(|_| anyhow::anyhow!("openai response body timed out"))?
+            .map_err(|e| e.into())
+    }
+}
+
+// prompt and answer together must fit in it, unknown models are taken as 4K
+pub fn context_window(model: &str) -> usize {
+    match model {
+        m if m.starts_with("gpt-4-32k") => 32768,
+        m if m.starts_with("gpt-4") => 8192,
+        m if m.starts_with("gpt-3.5-turbo-16k") => 16384,
+        _ => 4096,
+    }
+}
+
+fn is_transient(status: StatusCode, body: &str) -> bool {
+    (status == StatusCode::TOO_MANY_REQUESTS && !body.contains("insufficient_quota"))
+        || status.is_server_error()
+}
+
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let read = |name: &str| headers.get(name)?.to_str().ok()?.trim().parse::<f64>().ok();
+    if let Some(ms) = read("retry-after-ms") {
+        return Some(Duration::from_secs_f64(ms.max(0.0) / 1000.0));
+    }
+    read("retry-after").map(|s| Duration::from_secs_f64(s.max(0.0)))
+}
+
+// full jitter: random in [0, base * 2^attempt], but never earlier than the server asked for
+fn backoff(attempt: usize, retry_after: Option<Duration>) -> Duration {
+    let cap = BACKOFF_BASE_MS
+        .saturating_mul(1 << attempt.min(16))
+        .min(BACKOFF_MAX_MS);
+    let jitter = Duration::from_millis(rand::thread_rng().gen_range(0..=cap));
+    match retry_after {
+        Some(retry_after) => retry_after + jitter / 4,
+        None => jitter,
+    }
+}
+
+// parse `data: {...}` lines of a text/event-stream body until `data: [DONE]`, a stream silent
+// for longer than idle or ending without `[DONE]` is broken
+fn sse_stream(response: Response, idle: Duration) -> ChatStream {
+    let state = (response.bytes_stream(), Vec::<u8>::new(), false);
+    let stream = futures::stream::unfold(state, move |(mut body, mut buf, done)| async move {
+        if done {
+            return None;
+        }
+        loop {
+            if let Some(pos) = buf.iter().position(|b| *b == b'\n') {
+                let line = buf.drain(..=pos).collect::<Vec<_>>();
+                let line = String::from_utf8_lossy(&line);
+                let Some(data) = line.trim_end().strip_prefix("data:") else {
+                    continue;
+                };
+                let data = data.trim();
+                if data == "[DONE]" {
+                    return None;
+                }
+                let item = serde_json::from_str::<CreateChatCompletionStreamResponse>(data)
+                    .map_err(|e| anyhow::anyhow!("decode stream chunk {} failed: {}", data, e));
+                return Some((item, (body, buf, false)));
+            }
+            match tokio::time::timeout(idle, body.next()).await {
+                Ok(Some(Ok(bytes))) => buf.extend_from_slice(&bytes),
+                Ok(Some(Err(e))) => return Some((Err(e.into()), (body, buf, true))),
+                Ok(None) => {
+                    // a cut connection is not a complete answer
+                    let err = anyhow::anyhow!("stream ended before [DONE]");
+                    return Some((Err(err), (body, buf, true)));
+                }
+                Err(_) => {
+                    let err = anyhow::anyhow!("stream idle for {}s", idle.as_secs_f64());
+                    return Some((Err(err), (body, buf, true)));
+                }
+            }
+        }
+    });
+    Box::pin(stream)
+}
+
+// token buckets refilled continuously, capacity is one minute of budget, 0 means unlimited
+struct RateLimiter {
+    rpm: usize,
+    tpm: usize,
+    state: Mutex<LimiterState>,
+}
+
+struct LimiterState {
+    requests: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl RateLimiter {
+    fn new(rpm: usize, tpm: usize) -> Self {
+        Self {
+            rpm,
+            tpm,
+            state: Mutex::new(LimiterState {
+                requests: rpm as f64,
+                tokens: tpm as f64,
+                last: Instant::now(),
+            }),
+        }
+    }
+
+    async fn acquire(&self, tokens: usize) {
+        // a single request larger than the whole budget waits for a full bucket
+        let tokens = tokens.min(self.tpm) as f64;
+        loop {
+            let wait = {
+                let mut state = self.state.lock().await;
+                let elapsed = state.last.elapsed().as_secs_f64();
+                state.last = Instant::now();
+                state.requests =
+                    (state.requests + elapsed * self.rpm as f64 / 60.0).min(self.rpm as f64);
+                state.tokens =
+                    (state.tokens + elapsed * self.tpm as f64 / 60.0).min(self.tpm as f64);
+
+                let request_wait = if self.rpm == 0 || state.requests >= 1.0 {
+                    0.0
+                } else {
+                    (1.0 - state.requests) * 60.0 / self.rpm as f64
+                };
+                let token_wait = if self.tpm == 0 || state.tokens >= tokens {
+                    0.0
+                } else {
+                    (tokens - state.tokens) * 60.0 / self.tpm as f64
+                };
+                let wait = request_wait.max(token_wait);
+                if wait == 0.0 {
+                    if self.rpm != 0 {
+                        state.requests -= 1.0;
+                    }
+                    if self.tpm != 0 {
+                        state.tokens -= tokens;
+                    }
+                    return;
+                }
+                wait
+            };
+            debug!("rate limiter wait {}s", wait);
+            tokio::time::sleep(Duration::from_secs_f64(wait)).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use warp::Filter;
+
+    // a local server answering the n-th request with replies[n], the last reply repeats, a delay
+    // in ms is slept before answering
+    async fn mock_server(replies: Vec<(u16, &'static str, u64)>) -> (String, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let route = warp::post().then(move || {
+            let n = counter.fetch_add(1, Ordering::SeqCst);
+            let (status, body, delay) = replies[n.min(replies.len() - 1)];
+            async move {
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+                warp::http::Response::builder()
+                    .status(status)
+                    .header("retry-after-ms", "0")
+                    .body(body)
+                    .unwrap()
+            }
+        });
+        let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        (format!("http://{}/v1", addr), calls)
+    }
+
+    async fn post(api_base: &str, timeout: Duration) -> Result<Response> {
+        OpenAI::with_api_base(api_base, timeout)
+            .post("/embeddings", &serde_json::json!({}), 0)
+            .await
+    }
+
+    const CHUNK: &str = "data: {\"object\":\"chat.completion.chunk\",\"created\":0,\"model\":\"m\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"hi\"},\"finish_reason\":null}]}\n\n";
+
+    // a local server streaming chunks, each after delay ms, then either the end of the body or
+    // silence forever
+    async fn stream_server(chunks: Vec<&'static str>, delay: u64, hang: bool) -> String {
+        let route = warp::post().map(move || {
+            let body = futures::stream::iter(chunks.clone()).then(move |chunk| async move {
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+                Ok::<_, std::convert::Infallible>(chunk)
+            });
+            let body = if hang {
+                body.chain(futures::stream::pending()).boxed()
+            } else {
+                body.boxed()
+            };
+            warp::http::Response::new(warp::hyper::Body::wrap_stream(body))
+        });
+        let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        format!("http://{}/v1", addr)
+    }
+
+    async fn stream(api_base: &str, idle: Duration) -> Vec<Result<String>> {
+        let response = post(api_base, idle).await.unwrap();
+        sse_stream(response, idle)
+            .map(|item| item.map(|chunk| chunk.choices[0].delta.content.clone().unwrap()))
+            .collect()
+            .await
+    }
+
+    #[tokio::test]
+    async fn slow_streams_are_not_cut() {
+        // the whole answer takes longer than the timeout, but no single chunk does
+        let mut chunks = vec![CHUNK; 4];
+        chunks.push("data: [DONE]\n\n");
+        let api_base = stream_server(chunks, 100, false).await;
+        let items = stream(&api_base, Duration::from_millis(300)).await;
+        assert_eq!(items.len(), 4);
+        assert!(items.iter().all(|item| item.as_deref().ok() == Some("hi")));
+    }
+
+    #[tokio::test]
+    async fn truncated_streams_break() {
+        let api_base = stream_server(vec![CHUNK, CHUNK], 0, false).await;
+        let items = stream(&api_base, Duration::from_millis(300)).await;
+        assert_eq!(items.len(), 3);
+        assert!(items[..2].iter().all(|item| item.is_ok()));
+        assert!(items[2]
+            .as_ref()
+            .unwrap_err()
+            .to_string()
+            .contains("[DONE]"));
+    }
+
+    #[tokio::test]
+    async fn idle_streams_break() {
+        let api_base = stream_server(vec![CHUNK], 0, true).await;
+        let items = stream(&api_base, Duration::from_millis(300)).await;
+        assert_eq!(items.len(), 2);
+        assert!(items[0].is_ok());
+        assert!(items[1].as_ref().unwrap_err().to_string().contains("idle"));
+    }
+
+    #[tokio::test]
+    async fn retries_rate_limit_and_server_errors() {
+        let (api_base, calls) = mock_server(vec![
+            (429, "rate limited", 0),
+            (500, "server error", 0),
+            (503, "unavailable", 0),
+            (200, "{}", 0),
+        ])
+        .await;
+        let response = post(&api_base, Duration::from_secs(5)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn gives_up_on_client_errors() {
+        let (api_base, calls) = mock_server(vec![(400, "bad request", 0), (200, "{}", 0)]).await;
+        assert!(post(&api_base, Duration::from_secs(5)).await.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn gives_up_on_insufficient_quota() {
+        let body = r#"{"error":{"code":"insufficient_quota"}}"#;
+        let (api_base, calls) = mock_server(vec![(429, body, 0), (200, "{}", 0)]).await;
+        assert!(post(&api_base, Duration::from_secs(5)).await.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_retries() {
+        let (api_base, calls) = mock_server(vec![(502, "bad gateway", 0)]).await;
+        assert!(post(&api_base, Duration::from_secs(5)).await.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), *OPENAI_MAX_RETRIES + 1);
+    }
+
+    #[tokio::test]
+    async fn retries_timeouts() {
+        let (api_base, calls) = mock_server(vec![(200, "{}", 1000), (200, "{}", 0)]).await;
+        let response = post(&api_base, Duration::from_millis(200)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn limiter_is_unlimited_at_zero() {
+        let limiter = RateLimiter::new(0, 0);
+        let start = Instant::now();
+        for _ in 0..1000 {
+            limiter.acquire(100_000).await;
+        }
+        assert!(start.elapsed() < Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn limiter_waits_for_requests() {
+        // 2 requests per second, a full bucket at start
+        let limiter = RateLimiter::new(120, 0);
+        let start = Instant::now();
+        for _ in 0..120 {
+            limiter.acquire(0).await;
+        }
+        assert!(start.elapsed() < Duration::from_millis(100));
+        limiter.acquire(0).await;
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(400) && elapsed < Duration::from_millis(1500));
+    }
+
+    #[tokio::test]
+    async fn limiter_waits_for_tokens() {
+        // 100 tokens per second, a request larger than the budget takes the whole bucket
+        let limiter = RateLimiter::new(0, 6000);
+        let start = Instant::now();
+        limiter.acquire(10_000).await;
+        assert!(start.elapsed() < Duration::from_millis(100));
+        limiter.acquire(50).await;
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(400) && elapsed < Duration::from_millis(1500));
+    }
+
+    #[test]
+    fn backoff_honors_retry_after() {
+        for attempt in 0..10 {
+            let delay = backoff(attempt, Some(Duration::from_secs(3)));
+            assert!(delay >= Duration::from_secs(3));
+            assert!(backoff(attempt, None) <= Duration::from_millis(BACKOFF_MAX_MS));
+        }
+    }
+
+    #[test]
+    fn reads_retry_after_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert("retry-after", "2".parse().unwrap());
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(2)));
+        headers.insert("retry-after-ms", "1500".parse().unwrap());
+        assert_eq!(retry_after(&headers), Some(Duration::from_millis(1500)));
+    }
+}
@@ -0,0 +1,231 @@
+// prompt templates, loaded from ./prompts/[name].json, and ./prompts/[name].[code].json for
+// languages other than zh
+//
+// {
+//     "system": "...",
+//     "user": "...{context}...{question}..."
+// }
+//
+// placeholders:
+// {context} -> matched chunks
+// {question} -> user question
+// {sources} -> file name and location of the matched chunks
+// {history} -> previous turns of the conversation
+
+use super::language::Language;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+const PLACEHOLDERS: [&str; 4] = ["context", "question", "sources", "history"];
+const REQUIRED: [&str; 2] = ["context", "question"];
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct PromptTemplate {
+    #[serde(skip)]
+    pub name: String,
+    pub system: String,
+    pub user: String,
+}
+
+#[derive(Default)]
+pub struct PromptVars<'a> {
+    pub context: &'a str,
+    pub question: &'a str,
+    pub sources: &'a str,
+    pub history: &'a str,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Prompt {
+    pub system: String,
+    pub user: String,
+}
+
+impl PromptTemplate {
+    // templates of every language, a missing translation falls back to the zh template
+    pub fn load_all(name: &str) -> Result<HashMap<Language, Self>> {
+        let zh = Self::load(name)?;
+        let mut templates = HashMap::new();
+        for language in Language::ALL {
+            if language == Language::Zh {
+                continue;
+            }
+            let localized = name.to_string() + "." + language.code();
+            let template = if PathBuf::from("./prompts")
+                .join(localized.clone() + ".json")
+                .exists()
+            {
+                Self::load(&localized)?
+            } else {
+                warn!(
+                    "prompt template {} not found, use {} for {}",
+                    localized,
+                    name,
+                    language.code()
+                );
+                zh.clone()
+            };
+            templates.insert(language, template);
+        }
+        templates.insert(Language::Zh, zh);
+        Ok(templates)
+    }
+
+    pub fn load(name: &str) -> Result<Self> {
+        let path = PathBuf::from("./prompts").join(name.to_string() + ".json");
+        let content = std::fs::read_to_string(&path).map_err(|e| {
+            anyhow::anyhow!("read prompt template {} failed: {}", path.display(), e)
+        })?;
+        let mut template: PromptTemplate = serde_json::from_str(&content)
+            .map_err(|e| anyhow::anyhow!("parse prompt template {} failed: {}", name, e))?;
+        template.name = name.to_string();
+        template.validate()?;
+        Ok(template)
+    }
+
+    fn validate(&self) -> Result<()> {
+        let mut used = vec![];
+        for text in [&self.system, &self.user] {
+            for placeholder in placeholders(text) {
+                if !PLACEHOLDERS.contains(&placeholder) {
+                    return Err(anyhow::anyhow!(
+                        "prompt template {}: unknown placeholder {{{}}}",
+                        self.name,
+                        placeholder
+                    ));
+                }
+                used.push(placeholder);
+            }
+        }
+        for required in REQUIRED {
+            if !used.contains(&required) {
+                return Err(anyhow::anyhow!(
+                    "prompt template {}: missing placeholder {{{}}}",
+                    self.name,
+                    required
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn render(&self, vars: &PromptVars) -> Prompt {
+        Prompt {
+            system: vars.substitute(&self.system),
+            user: vars.substitute(&self.user),
+        }
+    }
+}
+
+impl PromptVars<'_> {
+    fn get(&self, name: &str) -> Option<&str> {
+        match name {
+            "context" => Some(self.context),
+            "question" => Some(self.question),
+            "sources" => Some(self.sources),
+            "history" => Some(self.history),
+            _ => None,
+        }
+    }
+
+    // a single pass over the template, placeholders inside the values are left as they are
+    fn substitute(&self, text: &str) -> String {
+        let mut result = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(start) = rest.find('{') {
+            result += &rest[..start];
+            rest = &rest[start + 1..];
+            let placeholder = rest
+                .find('}')
+                .and_then(|end| Some((end, self.get(&rest[..end])?)));
+            match placeholder {
+                Some((end, value)) => {
+                    result += value;
+                    rest = &rest[end + 1..];
+                }
+                None => result.push('{'),
+            }
+        }
+        result + rest
+    }
+}
+
+// names inside `{name}`, where name is made of lowercase letters and underscores
+fn placeholders(text: &str) -> Vec<&str> {
+    let mut result = vec![];
+    let mut rest = text;
+    while let Some(start) = rest.find('{') {
+        rest = &rest[start + 1..];
+        if let Some(end) = rest.find('}') {
+            let name = &rest[..end];
+            if !name.is_empty() && name.chars().all(|c| c.is_ascii_lowercase() || c == '_') {
+                result.push(name);
+                rest = &rest[end + 1..];
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn template(system: &str, user: &str) -> PromptTemplate {
+        PromptTemplate {
+            name: "test".to_string(),
+            system: system.to_string(),
+            user: user.to_string(),
+        }
+    }
+
+    #[test]
+    fn renders_every_placeholder() {
+        let prompt = template("history: {history}", "{context}\n{sources}\nQ: {question}").render(
+            &PromptVars {
+                context: "ctx",
+                question: "q",
+                sources: "src",
+                history: "h",
+            },
+        );
+        assert_eq!(prompt.system, "history: h");
+        assert_eq!(prompt.user, "ctx\nsrc\nQ: q");
+    }
+
+    #[test]
+    fn leaves_placeholders_inside_values() {
+        let prompt = template("", "{context} | {question} | {history}").render(&PromptVars {
+            context: "see {question} and {history}",
+            question: "what is {sources}?",
+            sources: "secret",
+            history: "",
+        });
+        assert_eq!(
+            prompt.user,
+            "see {question} and {history} | what is {sources}? | "
+        );
+    }
+
+    #[test]
+    fn keeps_other_braces() {
+        let prompt = template("", "{\"a\": 1} {x {context}} {").render(&PromptVars {
+            context: "c",
+            ..Default::default()
+        });
+        assert_eq!(prompt.user, "{\"a\": 1} {x c} {");
+    }
+
+    #[test]
+    fn validates_placeholders() {
+        assert!(template("", "{context} {question}").validate().is_ok());
+        assert!(template("{context}", "{question}").validate().is_ok());
+        assert!(template("", "{context}").validate().is_err());
+        assert!(template("", "{context} {question} {answer}")
+            .validate()
+            .is_err());
+        assert_eq!(placeholders("{a} {B} {} {b_c} {d"), vec!["a", "b_c"]);
+    }
+}
@@ -2,12 +2,20 @@
 //
 // pub struct UnLearnedChunk {
 //     pub content: String,
+//     pub section: String,
 //     pub page: usize,
+//     pub end_page: usize,
+//     pub paragraph: usize,
+//     pub end_paragraph: usize,
+//     pub char_start: usize,
+//     pub char_end: usize,
 // }
 //
 // pub struct UnLearnedKnowledge {
 //     pub file_name: String,
 //     pub uploader: String,
+//     pub metadata: BTreeMap<String, String>,
+//     pub unit_kind: UnitKind,
 //     pub chunks: Vec<UnLearnedChunk>,
 // }
 //
@@ -15,16 +23,25 @@
 // count -> count of knowledges
 // [i]/name -> i file_name
 // [i]/uploader -> uploader: String
+// [i]/metadata -> json map of document metadata, e.g. title, url, missing in old data
+// [i]/unit_kind -> json UnitKind, missing in old data
 // [i]/count -> count of chunks
 // [i]/[j]/vector -> j chunk vector
 // [i]/[j]/content -> j chunk content
+// [i]/[j]/section -> j chunk heading path, missing in old data and for files without headings
 // [i]/[j]/page -> j chunk page
+// [i]/[j]/end_page -> j chunk end page, missing in old data
+// [i]/[j]/paragraph -> j chunk paragraph in its start page, missing in old data
+// [i]/[j]/end_paragraph -> j chunk paragraph in its end page, missing in old data
+// [i]/[j]/char_start -> j chunk start offset in extracted text, missing in old data
+// [i]/[j]/char_end -> j chunk end offset in extracted text, missing in old data
+// cache/... -> embedding cache, see cache.rs
 //
 // writes should be mutually exclusive, but one write and some reads are allowed to be concurrent
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
-use crate::chunk_file::{UnLearnedChunk, UnLearnedKnowledge};
+use crate::chunk_file::{UnLearnedChunk, UnLearnedKnowledge, UnitKind};
 use anyhow::Result;
 use byteorder::{ByteOrder, LittleEndian};
 use opendal::services::Sled;
@@ -73,6 +90,18 @@ impl Storage {
                 unlearned_knowledge.uploader,
             )
             .await?;
+        self.operator
+            .write(
+                &(index.to_string() + "/metadata"),
+                serde_json::to_vec(&unlearned_knowledge.metadata)?,
+            )
+            .await?;
+        self.operator
+            .write(
+                &(index.to_string() + "/unit_kind"),
+                serde_json::to_vec(&unlearned_knowledge.unit_kind)?,
+            )
+            .await?;
         self.operator
             .write(
                 &(index.to_string() + "/count"),
@@ -92,15 +121,27 @@ impl Storage {
                     chunk.content.clone(),
                 )
                 .await?;
-            self.operator
-                .write(
-                    &(index.to_string() + "/" + &j.to_string() + "/page"),
-                    chunk.page.to_be_bytes().to_vec(),
-                )
-                .await?;
+            let prefix = index.to_string() + "/" + &j.to_string();
+            if !chunk.section.is_empty() {
+                self.operator
+                    .write(&(prefix.clone() + "/section"), chunk.section.clone())
+                    .await?;
+            }
+            for (key, value) in [
+                ("/page", chunk.page),
+                ("/end_page", chunk.end_page),
+                ("/paragraph", chunk.paragraph),
+                ("/end_paragraph", chunk.end_paragraph),
+                ("/char_start", chunk.char_start),
+                ("/char_end", chunk.char_end),
+            ] {
+                self.operator
+                    .write(&(prefix.clone() + key), value.to_be_bytes().to_vec())
+                    .await?;
+            }
             debug!(
-                "store chunk: j: {}, content: {}, page: {}",
-                j, chunk.content, chunk.page
+                "store chunk: j: {}, content: {}, page: {}-{}, chars: {}-{}",
+                j, chunk.content, chunk.page, chunk.end_page, chunk.char_start, chunk.char_end
             );
         }
         self.operator
@@ -123,6 +164,18 @@ impl Storage {
                 .await?,
         );
 
+        let metadata = match self.operator.read(&(index.to_string() + "/metadata")).await {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(_) => BTreeMap::new(),
+        };
+        let unit_kind = match self
+            .operator
+            .read(&(index.to_string() + "/unit_kind"))
+            .await
+        {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(_) => UnitKind::guess(&file_name),
+        };
         let mut chunks = vec![];
         for j in vector_indexs.iter() {
             let content = string_decode(
@@ -137,19 +190,58 @@ impl Storage {
                     .read(&(index.to_string() + "/" + &j.to_string() + "/page"))
                     .await?,
             );
+            let prefix = index.to_string() + "/" + &j.to_string();
+            let end_page = self
+                .read_usize_or(&(prefix.clone() + "/end_page"), page)
+                .await;
+            let paragraph = self
+                .read_usize_or(&(prefix.clone() + "/paragraph"), 0)
+                .await;
+            let end_paragraph = self
+                .read_usize_or(&(prefix.clone() + "/end_paragraph"), paragraph)
+                .await;
+            let char_start = self
+                .read_usize_or(&(prefix.clone() + "/char_start"), 0)
+                .await;
+            let char_end = self.read_usize_or(&(prefix.clone() + "/char_end"), 0).await;
+            let section = self
+                .operator
+                .read(&(prefix + "/section"))
+                .await
+                .map(|bytes| string_decode(&bytes))
+                .unwrap_or_default();
             debug!("load chunk: j: {}, content: {}", j, content);
-            let chunk = UnLearnedChunk { content, page };
+            let chunk = UnLearnedChunk {
+                content,
+                section,
+                page,
+                end_page,
+                paragraph,
+                end_paragraph,
+                char_start,
+                char_end,
+            };
             chunks.push(chunk);
         }
         let unlearned_knowledge = UnLearnedKnowledge {
             file_name,
             uploader,
+            metadata,
+            unit_kind,
             chunks,
         };
 
         Ok(unlearned_knowledge)
     }
 
+    async fn read_usize_or(&self, path: &str, default: usize) -> usize {
+        self.operator
+            .read(path)
+            .await
+            .map(|bytes| usize_decode(&bytes))
+            .unwrap_or(default)
+    }
+
     pub async fn get_vectors(&self) -> Result<HashMap<usize, Vec<Vec<f32>>>> {
         let mut map = HashMap::new();
         let index = usize_decode(
@@ -177,6 +269,24 @@ impl Storage {
         Ok(map)
     }
 
+    // missing in old data
+    pub async fn get_metadata(&self) -> Result<HashMap<usize, BTreeMap<String, String>>> {
+        let mut map = HashMap::new();
+        let index = usize_decode(
+            &self
+                .operator
+                .read("count")
+                .await
+                .unwrap_or(0usize.to_be_bytes().to_vec()),
+        );
+        for i in 0..index {
+            if let Ok(bytes) = self.operator.read(&(i.to_string() + "/metadata")).await {
+                map.insert(i, serde_json::from_slice(&bytes)?);
+            }
+        }
+        Ok(map)
+    }
+
     pub async fn get_list(&self) -> Result<Vec<String>> {
         let mut list = vec![];
         let index = usize_decode(
@@ -194,7 +304,7 @@ impl Storage {
     }
 }
 
-fn usize_decode(data: &[u8]) -> usize {
+pub fn usize_decode(data: &[u8]) -> usize {
     usize::from_be_bytes(data.try_into().unwrap())
 }
 
@@ -203,13 +313,13 @@ pub fn string_decode(bytes: &[u8]) -> String {
     s.to_string()
 }
 
-fn float_to_bytes(float_vec: &[f32]) -> Vec<u8> {
+pub fn float_to_bytes(float_vec: &[f32]) -> Vec<u8> {
     let mut byte_vec = vec![0u8; float_vec.len() * 4];
     LittleEndian::write_f32_into(float_vec, &mut byte_vec);
     byte_vec
 }
 
-fn bytes_to_float(byte_vec: &[u8]) -> Vec<f32> {
+pub fn bytes_to_float(byte_vec: &[u8]) -> Vec<f32> {
     let mut float_vec = vec![0f32; byte_vec.len() / 4];
     LittleEndian::read_f32_into(byte_vec, &mut float_vec);
     float_vec
@@ -0,0 +1,362 @@
+// token usage and cost accounting, shares the sled of storage
+//
+// every openai call adds its usage to the Ledger of the current query or document,
+// the ledger is committed once the work is done
+//
+// usage protocol:
+// usage/days -> json array of dates with usage
+// usage/day/[yyyy-mm-dd] -> json map of model -> ModelUsage
+// usage/query/count -> count of recorded queries
+// usage/query/[n] -> json UsageRecord of the n-th query
+// usage/document/[file_name] -> json UsageRecord of indexing the file, summed over retries
+//
+// prices are read from ./prices.json, USD per 1K tokens:
+// { "gpt-3.5-turbo": { "prompt": 0.0015, "completion": 0.002 } }
+
+use super::storage::usize_decode;
+use crate::MONTHLY_BUDGET;
+use anyhow::Result;
+use chrono::Local;
+use opendal::Operator;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+pub struct ModelUsage {
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+    pub requests: usize,
+    // counted locally because the response carries no usage
+    pub estimated_tokens: usize,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default)]
+pub struct Price {
+    pub prompt: f64,
+    pub completion: f64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UsageRecord {
+    pub time: String,
+    pub name: String,
+    pub models: BTreeMap<String, ModelUsage>,
+    pub cost: f64,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct DayUsage {
+    pub date: String,
+    pub models: BTreeMap<String, ModelUsage>,
+    pub cost: f64,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct UsageReport {
+    pub days: Vec<DayUsage>,
+    pub month: String,
+    pub month_cost: f64,
+    // 0 means no budget
+    pub monthly_budget: f64,
+}
+
+pub enum UsageKind {
+    Query,
+    Document,
+}
+
+// usage of one query or document, filled while the work is in flight
+#[derive(Default)]
+pub struct Ledger {
+    models: Mutex<BTreeMap<String, ModelUsage>>,
+}
+
+impl Ledger {
+    pub fn add(
+        &self,
+        model: &str,
+        prompt_tokens: usize,
+        completion_tokens: usize,
+        estimated: bool,
+    ) {
+        let mut models = self.models.lock().unwrap();
+        let usage = models.entry(model.to_string()).or_default();
+        usage.prompt_tokens += prompt_tokens;
+        usage.completion_tokens += completion_tokens;
+        usage.requests += 1;
+        if estimated {
+            usage.estimated_tokens += prompt_tokens + completion_tokens;
+        }
+    }
+
+    pub fn models(&self) -> BTreeMap<String, ModelUsage> {
+        self.models.lock().unwrap().clone()
+    }
+}
+
+#[derive(Clone)]
+pub struct UsageMeter {
+    operator: Operator,
+    prices: HashMap<String, Price>,
+    // (yyyy-mm, cost), kept in memory for the budget check
+    month: Arc<Mutex<(String, f64)>>,
+    write_lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl UsageMeter {
+    // models are the ones this server calls, without a price the budget can not be enforced
+    pub async fn new(operator: Operator, models: &[&str]) -> Result<Self> {
+        let prices = match std::fs::read_to_string("./prices.json") {
+            Ok(content) => serde_json::from_str(&content)
+                .map_err(|e| anyhow::anyhow!("parse prices.json failed: {}", e))?,
+            Err(e) => {
+                warn!("read prices.json failed: {}, costs are counted as 0", e);
+                HashMap::new()
+            }
+        };
+        let missing = missing_prices(&prices, models);
+        if !missing.is_empty() {
+            if *MONTHLY_BUDGET > 0.0 {
+                return Err(anyhow::anyhow!(
+                    "no price of {} in prices.json, MONTHLY_BUDGET can not be enforced",
+                    missing.join(", ")
+                ));
+            }
+            warn!(
+                "no price of {} in prices.json, costs are counted as 0",
+                missing.join(", ")
+            );
+        }
+        Self::with_prices(operator, prices).await
+    }
+
+    async fn with_prices(operator: Operator, prices: HashMap<String, Price>) -> Result<Self> {
+        let meter = Self {
+            operator,
+            prices,
+            month: Arc::new(Mutex::new((String::new(), 0.0))),
+            write_lock: Arc::new(tokio::sync::Mutex::new(())),
+        };
+        let month = Local::now().format("%Y-%m").to_string();
+        let mut month_cost = 0.0;
+        for date in meter.days().await? {
+            if date.starts_with(&month) {
+                month_cost += meter.cost(&meter.day(&date).await?);
+            }
+        }
+        info!("usage meter init, month: {}, cost: {}", month, month_cost);
+        *meter.month.lock().unwrap() = (month, month_cost);
+        Ok(meter)
+    }
+
+    pub fn cost(&self, models: &BTreeMap<String, ModelUsage>) -> f64 {
+        models
+            .iter()
+            .map(|(model, usage)| {
+                let price = self.prices.get(model).copied().unwrap_or_default();
+                (usage.prompt_tokens as f64 * price.prompt
+                    + usage.completion_tokens as f64 * price.completion)
+                    / 1000.0
+            })
+            .sum()
+    }
+
+    // rejects new work once the monthly budget is used up
+    pub fn check_budget(&self) -> Result<()> {
+        if *MONTHLY_BUDGET <= 0.0 {
+            return Ok(());
+        }
+        let month = Local::now().format("%Y-%m").to_string();
+        let mut current = self.month.lock().unwrap();
+        if current.0 != month {
+            *current = (month, 0.0);
+        }
+        if current.1 >= *MONTHLY_BUDGET {
+            return Err(anyhow::anyhow!(
+                "monthly budget exceeded: {:.4} / {:.4}",
+                current.1,
+                *MONTHLY_BUDGET
+            ));
+        }
+        Ok(())
+    }
+
+    pub async fn commit(&self, kind: UsageKind, name: &str, ledger: &Ledger) -> Result<f64> {
+        let models = ledger.models();
+        if models.is_empty() {
+            return Ok(0.0);
+        }
+        let cost = self.cost(&models);
+        let now = Local::now();
+        let date = now.format("%Y-%m-%d").to_string();
+        let mut record = UsageRecord {
+            time: now.to_rfc3339(),
+            name: name.to_string(),
+            models: models.clone(),
+            cost,
+        };
+
+        let _guard = self.write_lock.lock().await;
+        // daily totals
+        let mut days = self.days().await?;
+        if !days.contains(&date) {
+            days.push(date.clone());
+            self.operator
+                .write("usage/days", serde_json::to_vec(&days)?)
+                .await?;
+        }
+        let mut day = self.day(&date).await?;
+        add_models(&mut day, &models);
+        self.operator
+            .write(
+                &("usage/day/".to_string() + &date),
+                serde_json::to_vec(&day)?,
+            )
+            .await?;
+
+        // per query or per document
+        match kind {
+            UsageKind::Query => {
+                let count = usize_decode(
+                    &self
+                        .operator
+                        .read("usage/query/count")
+                        .await
+                        .unwrap_or(0usize.to_be_bytes().to_vec()),
+                );
+                self.operator
+                    .write(
+                        &("usage/query/".to_string() + &count.to_string()),
+                        serde_json::to_vec(&record)?,
+                    )
+                    .await?;
+                self.operator
+                    .write("usage/query/count", (count + 1).to_be_bytes().to_vec())
+                    .await?;
+            }
+            UsageKind::Document => {
+                // a retried or reindexed file adds to its record
+                let path = "usage/document/".to_string() + name;
+                if let Ok(bytes) = self.operator.read(&path).await {
+                    let previous: UsageRecord = serde_json::from_slice(&bytes)?;
+                    add_models(&mut record.models, &previous.models);
+                    record.cost += previous.cost;
+                }
+                self.operator
+                    .write(&path, serde_json::to_vec(&record)?)
+                    .await?;
+            }
+        }
+
+        {
+            let month = now.format("%Y-%m").to_string();
+            let mut current = self.month.lock().unwrap();
+            if current.0 != month {
+                *current = (month, 0.0);
+            }
+            current.1 += cost;
+        }
+        debug!("usage commit: {:?}", record);
+        Ok(cost)
+    }
+
+    // the latest n days with usage
+    pub async fn report(&self, n: usize) -> Result<UsageReport> {
+        let mut days = self.days().await?;
+        days.sort();
+        let mut result = vec![];
+        for date in days.iter().rev().take(n) {
+            let models = self.day(date).await?;
+            let cost = self.cost(&models);
+            result.push(DayUsage {
+                date: date.clone(),
+                models: models.into_iter().collect(),
+                cost,
+            });
+        }
+        let (month, month_cost) = self.month.lock().unwrap().clone();
+        Ok(UsageReport {
+            days: result,
+            month,
+            month_cost,
+            monthly_budget: *MONTHLY_BUDGET,
+        })
+    }
+
+    async fn days(&self) -> Result<Vec<String>> {
+        match self.operator.read("usage/days").await {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            Err(_) => Ok(vec![]),
+        }
+    }
+
+    async fn day(&self, date: &str) -> Result<BTreeMap<String, ModelUsage>> {
+        match self.operator.read(&("usage/day/".to_string() + date)).await {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            Err(_) => Ok(BTreeMap::new()),
+        }
+    }
+}
+
+fn add_models(total: &mut BTreeMap<String, ModelUsage>, models: &BTreeMap<String, ModelUsage>) {
+    for (model, usage) in models.iter() {
+        let total = total.entry(model.clone()).or_default();
+        total.prompt_tokens += usage.prompt_tokens;
+        total.completion_tokens += usage.completion_tokens;
+        total.requests += usage.requests;
+        total.estimated_tokens += usage.estimated_tokens;
+    }
+}
+
+fn missing_prices<'a>(prices: &HashMap<String, Price>, models: &[&'a str]) -> Vec<&'a str> {
+    models
+        .iter()
+        .filter(|model| !prices.contains_key(**model))
+        .copied()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opendal::services::Memory;
+
+    fn prices() -> HashMap<String, Price> {
+        let price = Price {
+            prompt: 1.0,
+            completion: 2.0,
+        };
+        HashMap::from([("chat".to_string(), price)])
+    }
+
+    #[test]
+    fn finds_models_without_price() {
+        assert_eq!(
+            missing_prices(&prices(), &["chat", "embedding"]),
+            vec!["embedding"]
+        );
+        assert!(missing_prices(&prices(), &["chat"]).is_empty());
+    }
+
+    #[tokio::test]
+    async fn accumulates_document_retries() {
+        let operator = Operator::new(Memory::default()).unwrap().finish();
+        let meter = UsageMeter::with_prices(operator, prices()).await.unwrap();
+        for _ in 0..2 {
+            let ledger = Ledger::default();
+            ledger.add("chat", 1000, 500, false);
+            meter
+                .commit(UsageKind::Document, "a.pdf", &ledger)
+                .await
+                .unwrap();
+        }
+        let bytes = meter.operator.read("usage/document/a.pdf").await.unwrap();
+        let record: UsageRecord = serde_json::from_slice(&bytes).unwrap();
+        let usage = record.models["chat"];
+        assert_eq!(usage.prompt_tokens, 2000);
+        assert_eq!(usage.completion_tokens, 1000);
+        assert_eq!(usage.requests, 2);
+        assert!((record.cost - 4.0).abs() < 1e-9);
+    }
+}
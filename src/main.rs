@@ -1,29 +1,43 @@
 mod chunk_file;
 mod knowledge;
+mod viewer;
 
-use crate::chunk_file::match_file;
+use crate::chunk_file::{archive, extract, match_file};
 use anyhow::Result;
 use chunk_file::UnlearnedFile;
 use dotenv::dotenv;
 use env_logger::Builder;
-use futures::StreamExt;
+use futures::{SinkExt, StreamExt};
 use futures_util::stream::TryStreamExt;
 use knowledge::brain::Brain;
+use knowledge::conversation::Conversation;
+use knowledge::event::{Answer, ClientMessage, Protocol, QueryEvent};
+use knowledge::expansion::Expansion;
+use knowledge::language::Language;
+use knowledge::prompt::PromptTemplate;
 use lazy_static::lazy_static;
 use log::LevelFilter;
 use pdfium_render::prelude::Pdfium;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::convert::Infallible;
 use std::error::Error;
 use std::path::PathBuf;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tiktoken_rs::cl100k_base;
 use tokio::io::AsyncWriteExt;
 use tokio::{
     fs,
-    sync::mpsc::{channel, Receiver, Sender},
+    sync::mpsc::{channel, unbounded_channel, Receiver, Sender},
+};
+use tokio_util::sync::CancellationToken;
+use warp::{
+    http::StatusCode,
+    multipart::FormData,
+    ws::{Message, WebSocket},
+    Buf, Filter, Rejection, Reply,
 };
-use warp::{multipart::FormData, ws::WebSocket, Buf, Filter, Rejection, Reply};
 
 #[macro_use]
 extern crate log;
@@ -41,11 +55,104 @@ lazy_static! {
         .unwrap()
         .parse::<usize>()
         .unwrap();
+    static ref OPENAI_API_BASE: String = std::env::var("OPENAI_API_BASE")
+        .unwrap_or("https://api.openai.com/v1".to_string());
+    static ref OPENAI_MAX_RETRIES: usize = std::env::var("OPENAI_MAX_RETRIES")
+        .map(|v| v.parse::<usize>().unwrap())
+        .unwrap_or(5);
+    // seconds to wait for an openai response to start, and between chunks of a streamed answer
+    static ref OPENAI_TIMEOUT: u64 = std::env::var("OPENAI_TIMEOUT")
+        .map(|v| v.parse::<u64>().unwrap())
+        .unwrap_or(120);
+    // client-side rate limit, 0 means unlimited
+    static ref OPENAI_RPM: usize = std::env::var("OPENAI_RPM")
+        .map(|v| v.parse::<usize>().unwrap())
+        .unwrap_or(0);
+    static ref OPENAI_TPM: usize = std::env::var("OPENAI_TPM")
+        .map(|v| v.parse::<usize>().unwrap())
+        .unwrap_or(0);
+    // chat model of answers, rewrites and expansions, also decides the prompt budget
+    static ref CHAT_MODEL: String =
+        std::env::var("CHAT_MODEL").unwrap_or("gpt-3.5-turbo".to_string());
+    // max entries of the embedding cache, 0 disables it
+    static ref EMBEDDING_CACHE_SIZE: usize = std::env::var("EMBEDDING_CACHE_SIZE")
+        .map(|v| v.parse::<usize>().unwrap())
+        .unwrap_or(100000);
+    // name of the prompt template in ./prompts
+    static ref PROMPT_TEMPLATE: String =
+        std::env::var("PROMPT_TEMPLATE").unwrap_or("default".to_string());
+    // token budget of the conversation history included in the prompt
+    static ref HISTORY_TOKENS: usize = std::env::var("HISTORY_TOKENS")
+        .map(|v| v.parse::<usize>().unwrap())
+        .unwrap_or(1000);
+    // off, paraphrase or hyde
+    static ref QUERY_EXPANSION: Expansion = std::env::var("QUERY_EXPANSION")
+        .map(|v| v.parse::<Expansion>().unwrap())
+        .unwrap_or(Expansion::Off);
+    static ref QUERY_VARIANTS: usize = std::env::var("QUERY_VARIANTS")
+        .map(|v| v.parse::<usize>().unwrap())
+        .unwrap_or(3);
+    // how many times a file failed to index is re-queued
+    static ref INDEX_RETRIES: usize = std::env::var("INDEX_RETRIES")
+        .map(|v| v.parse::<usize>().unwrap())
+        .unwrap_or(3);
+    // USD per month, new queries and uploads are rejected once it is used up, 0 means no cap,
+    // a cap needs prices.json to price every model in use
+    static ref MONTHLY_BUDGET: f64 = std::env::var("MONTHLY_BUDGET")
+        .map(|v| v.parse::<f64>().unwrap())
+        .unwrap_or(0.0);
+    // bearer token of /api/admin/*, which is disabled while it is empty
+    static ref ADMIN_TOKEN: String = std::env::var("ADMIN_TOKEN").unwrap_or_default();
+    // limits of an uploaded zip, bytes are the expanded size of all members
+    static ref ARCHIVE_MAX_ENTRIES: usize = std::env::var("ARCHIVE_MAX_ENTRIES")
+        .map(|v| v.parse::<usize>().unwrap())
+        .unwrap_or(1000);
+    static ref ARCHIVE_MAX_BYTES: u64 = std::env::var("ARCHIVE_MAX_BYTES")
+        .map(|v| v.parse::<u64>().unwrap())
+        .unwrap_or(500 << 20);
 }
 
+// language is detected from the query when missing
 #[derive(Deserialize, Serialize)]
 struct QueryRequest {
     query: String,
+    language: Option<Language>,
+    // same as the filter of search
+    #[serde(default)]
+    filter: knowledge::filter::Filter,
+}
+
+#[derive(Deserialize, Serialize)]
+struct SearchRequest {
+    query: String,
+    top_k: Option<usize>,
+    // e.g. sender and date of emails
+    #[serde(default)]
+    filter: knowledge::filter::Filter,
+}
+
+#[derive(Deserialize, Serialize)]
+struct UsageRequest {
+    days: Option<usize>,
+}
+
+// either a page, or a stored chunk whose pages are shown with the chunk highlighted
+#[derive(Deserialize, Serialize)]
+struct ViewRequest {
+    file: String,
+    page: Option<usize>,
+    index: Option<usize>,
+    chunk: Option<usize>,
+}
+
+// a session may start with a question in the url, later ones are sent as messages
+// protocol is json by default, `protocol=text` keeps the plain text frames
+// language applies to every question of the session unless a message names its own
+#[derive(Deserialize, Serialize)]
+struct SessionRequest {
+    query: Option<String>,
+    protocol: Option<String>,
+    language: Option<Language>,
 }
 
 #[tokio::main]
@@ -72,6 +179,23 @@ async fn main() -> Result<(), Box<dyn Error>> {
         env_logger::init();
     }
 
+    // statics are parsed lazily, a bad value fails here instead of on the first query or upload
+    lazy_static::initialize(&CHUNK_TOKENS);
+    lazy_static::initialize(&CHUNK_HEAD);
+    lazy_static::initialize(&CHUNK_TAIL);
+    lazy_static::initialize(&OPENAI_MAX_RETRIES);
+    lazy_static::initialize(&OPENAI_RPM);
+    lazy_static::initialize(&OPENAI_TPM);
+    lazy_static::initialize(&INDEX_RETRIES);
+    lazy_static::initialize(&EMBEDDING_CACHE_SIZE);
+    lazy_static::initialize(&HISTORY_TOKENS);
+    lazy_static::initialize(&QUERY_EXPANSION);
+    lazy_static::initialize(&QUERY_VARIANTS);
+    lazy_static::initialize(&MONTHLY_BUDGET);
+    lazy_static::initialize(&ARCHIVE_MAX_ENTRIES);
+    lazy_static::initialize(&ARCHIVE_MAX_BYTES);
+    lazy_static::initialize(&OPENAI_TIMEOUT);
+
     // check dependencies
     Pdfium::bind_to_library("./libpdfium.so")?;
     let upload_path = PathBuf::from("./files");
@@ -79,16 +203,18 @@ async fn main() -> Result<(), Box<dyn Error>> {
         fs::create_dir("./files").await?;
     }
     cl100k_base()?;
+    let templates = PromptTemplate::load_all(&PROMPT_TEMPLATE)?;
     info!("dependencies check succeed");
 
     let (file_sender, file_receiver) = channel(1);
 
-    let brain = Arc::new(Brain::new("test".to_string(), "wjj".to_string()).await);
+    let brain = Arc::new(Brain::new("test".to_string(), "wjj".to_string(), templates).await);
     let brain_for_query = Arc::clone(&brain);
     let brain_for_index = Arc::clone(&brain);
+    let file_sender_for_index = file_sender.clone();
 
     tokio::spawn(async move {
-        indexer(file_receiver, brain_for_index).await;
+        indexer(file_receiver, file_sender_for_index, brain_for_index).await;
     });
 
     // if a file is uploaded but not indexed, re-index it
@@ -100,27 +226,89 @@ async fn main() -> Result<(), Box<dyn Error>> {
         .collect::<Vec<String>>();
     for file_name in unindexed {
         let file_path = PathBuf::from("./files").join(file_name.clone());
-        let file = match_file(file_name.clone(), "".to_string(), file_path);
-        let _ = file_sender.send(file).await;
-        info!("send re-index to indexer: {}", file_name);
+        match match_file(file_name.clone(), "".to_string(), file_path, None) {
+            Ok(file) => {
+                let _ = file_sender.send(file).await;
+                info!("send re-index to indexer: {}", file_name);
+            }
+            Err(e) => warn!("skip re-index {}: {}", file_name, e),
+        }
     }
 
     let index_route = warp::path::end().and(warp::get()).and_then(index);
 
+    let brain_for_upload = Arc::clone(&brain);
     let file_upload_route = warp::path("upload")
         .and(warp::post())
         .and(warp::multipart::form())
         .and(warp::any().map(move || file_sender.clone()))
+        .and(warp::any().map(move || Arc::clone(&brain_for_upload)))
         .and_then(handle_upload);
 
     let query_route = warp::path("ws")
         .and(warp::ws())
-        .and(warp::query::<QueryRequest>())
+        .and(warp::query::<SessionRequest>())
         .and(warp::any().map(move || Arc::clone(&brain_for_query)))
-        .map(|ws: warp::ws::Ws, query: QueryRequest, brain| {
-            ws.on_upgrade(move |socket| handle_query(query, brain, socket))
+        .map(|ws: warp::ws::Ws, request: SessionRequest, brain| {
+            ws.on_upgrade(move |socket| handle_session(request, brain, socket))
         });
 
+    let brain_for_dry_run = Arc::clone(&brain);
+    let dry_run_route = warp::path!("api" / "dry_run")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(warp::any().map(move || Arc::clone(&brain_for_dry_run)))
+        .and_then(handle_dry_run);
+
+    let brain_for_api_query = Arc::clone(&brain);
+    let api_query_route = warp::path!("api" / "query")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(warp::any().map(move || Arc::clone(&brain_for_api_query)))
+        .and_then(handle_api_query);
+
+    let brain_for_api_stream = Arc::clone(&brain);
+    let api_stream_route = warp::path!("api" / "query" / "stream")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(warp::any().map(move || Arc::clone(&brain_for_api_stream)))
+        .and_then(handle_api_stream);
+
+    let brain_for_search = Arc::clone(&brain);
+    let search_route = warp::path!("api" / "search")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(warp::any().map(move || Arc::clone(&brain_for_search)))
+        .and_then(handle_search);
+
+    // uploaded files are untrusted, a browser must save them instead of rendering html or svg
+    // from our origin
+    let download_route = warp::path("files")
+        .and(warp::fs::dir("./files"))
+        .with(warp::reply::with::header(
+            "content-disposition",
+            "attachment",
+        ))
+        .with(warp::reply::with::header(
+            "x-content-type-options",
+            "nosniff",
+        ));
+
+    let brain_for_view = Arc::clone(&brain);
+    let view_route = warp::path("view")
+        .and(warp::get())
+        .and(warp::query::<ViewRequest>())
+        .and(warp::any().map(move || Arc::clone(&brain_for_view)))
+        .and_then(handle_view);
+
+    let brain_for_usage = Arc::clone(&brain);
+    let usage_route = warp::path!("api" / "admin" / "usage")
+        .and(warp::get())
+        .and(warp::query::<UsageRequest>())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(warp::any().map(move || Arc::clone(&brain_for_usage)))
+        .and_then(handle_usage);
+
     let get_list_route = warp::path("get_list")
         .and(warp::get())
         .and(warp::any().map(move || Arc::clone(&brain)))
@@ -129,7 +317,14 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let routes = index_route
         .or(query_route)
         .or(file_upload_route)
-        .or(get_list_route);
+        .or(get_list_route)
+        .or(dry_run_route)
+        .or(api_query_route)
+        .or(api_stream_route)
+        .or(search_route)
+        .or(download_route)
+        .or(view_route)
+        .or(usage_route);
 
     info!("server running at port: 8080");
     warp::serve(routes).run(([0, 0, 0, 0], 8080)).await;
@@ -149,20 +344,347 @@ async fn handle_get_list(brain: Arc<Brain>) -> Result<impl Reply, Rejection> {
     Ok(warp::reply::html(result))
 }
 
-async fn handle_query(query_request: QueryRequest, brain: Arc<Brain>, ws: WebSocket) {
-    info!("get query request: {:?}", query_request.query.clone());
+async fn handle_session(request: SessionRequest, brain: Arc<Brain>, ws: WebSocket) {
+    let protocol = match request.protocol.as_deref() {
+        Some("text") => Protocol::Text,
+        _ => Protocol::Json,
+    };
+    let (tx, mut rx) = ws.split();
+    let mut tx = tx.with_flat_map(move |event: QueryEvent| {
+        futures::stream::iter(event.encode(protocol).map(|s| Ok(Message::text(s))))
+    });
+
+    // read in another task, so that cancel and disconnect are seen while answering. every query
+    // gets its token when it is read, as a child of the session token, a cancel cancels the
+    // answering query and the queued ones, later queries get a new session token
+    let (query_sender, mut query_receiver) = unbounded_channel();
+    let session = Arc::new(Mutex::new(CancellationToken::new()));
+    let mut pending = request.query.map(|query| {
+        let cancel = session.lock().unwrap().child_token();
+        (query, None, knowledge::filter::Filter::default(), cancel)
+    });
+    let session_for_reader = Arc::clone(&session);
+    let reader = tokio::spawn(async move {
+        while let Some(result) = rx.next().await {
+            match result {
+                Ok(message) if message.is_text() => {
+                    match ClientMessage::parse(message.to_str().unwrap_or_default()) {
+                        ClientMessage::Query {
+                            query,
+                            language,
+                            filter,
+                        } => {
+                            let cancel = session_for_reader.lock().unwrap().child_token();
+                            let _ = query_sender.send((query, language, filter, cancel));
+                        }
+                        ClientMessage::Cancel => {
+                            info!("session get cancel request");
+                            let mut session = session_for_reader.lock().unwrap();
+                            session.cancel();
+                            *session = CancellationToken::new();
+                        }
+                    }
+                }
+                Ok(message) if message.is_close() => break,
+                Ok(_) => continue,
+                Err(e) => {
+                    warn!("session receive failed: {}", e);
+                    break;
+                }
+            }
+        }
+        info!("session client disconnected");
+        session_for_reader.lock().unwrap().cancel();
+    });
 
-    let (mut tx, _) = ws.split();
+    let mut conversation = Conversation::default();
+    loop {
+        let (query, language, filter, cancel) = match pending.take() {
+            Some(pending) => pending,
+            None => match query_receiver.recv().await {
+                Some(query) => query,
+                None => break,
+            },
+        };
+        if query.trim().is_empty() {
+            continue;
+        }
+        info!("get query request: {:?}", query);
 
-    if let Err(e) = brain.query(query_request.query, &mut tx).await {
-        warn!("handle query request failed: {}", e);
+        match brain
+            .query(
+                query.clone(),
+                language.or(request.language),
+                &filter,
+                &conversation,
+                &mut tx,
+                &cancel,
+            )
+            .await
+        {
+            Ok(answer) => conversation.push(query, answer),
+            Err(e) => warn!("handle query request failed: {}", e),
+        }
+        if reader.is_finished() {
+            break;
+        }
+    }
+    reader.abort();
+    info!("session closed");
+}
+
+async fn handle_dry_run(
+    query_request: QueryRequest,
+    brain: Arc<Brain>,
+) -> Result<impl Reply, Rejection> {
+    info!("get dry run request: {:?}", query_request.query);
+    match brain
+        .dry_run(
+            &query_request.query,
+            query_request.language,
+            &query_request.filter,
+        )
+        .await
+    {
+        Ok(prepared) => Ok(warp::reply::with_status(
+            warp::reply::json(&prepared),
+            StatusCode::OK,
+        )),
+        Err(e) => {
+            warn!("handle dry run request failed: {}", e);
+            Ok(warp::reply::with_status(
+                warp::reply::json(&serde_json::json!({ "error": e.to_string() })),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ))
+        }
+    }
+}
+
+async fn handle_api_query(
+    query_request: QueryRequest,
+    brain: Arc<Brain>,
+) -> Result<impl Reply, Rejection> {
+    info!("get api query request: {:?}", query_request.query);
+    let (mut tx, rx) = futures::channel::mpsc::unbounded();
+    // a disconnected client drops this future, which cancels everything in flight
+    let status = match brain
+        .query(
+            query_request.query,
+            query_request.language,
+            &query_request.filter,
+            &Conversation::default(),
+            &mut tx,
+            &CancellationToken::new(),
+        )
+        .await
+    {
+        Ok(_) => StatusCode::OK,
+        Err(e) => {
+            warn!("handle api query request failed: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
     };
+    drop(tx);
+    let mut answer = Answer::default();
+    rx.for_each(|event| {
+        answer.push(event);
+        futures::future::ready(())
+    })
+    .await;
+    Ok(warp::reply::with_status(warp::reply::json(&answer), status))
+}
+
+async fn handle_api_stream(
+    query_request: QueryRequest,
+    brain: Arc<Brain>,
+) -> Result<impl Reply, Rejection> {
+    info!("get api stream request: {:?}", query_request.query);
+    let (mut tx, rx) = futures::channel::mpsc::unbounded();
+    // a disconnected client drops rx, then sending fails and the query stops
+    tokio::spawn(async move {
+        if let Err(e) = brain
+            .query(
+                query_request.query,
+                query_request.language,
+                &query_request.filter,
+                &Conversation::default(),
+                &mut tx,
+                &CancellationToken::new(),
+            )
+            .await
+        {
+            warn!("handle api stream request failed: {}", e);
+        }
+    });
+    let events = rx.filter_map(|event: QueryEvent| {
+        futures::future::ready(event.encode(Protocol::Json).map(|data| {
+            Ok::<_, Infallible>(warp::sse::Event::default().event(event.name()).data(data))
+        }))
+    });
+    Ok(warp::sse::reply(warp::sse::keep_alive().stream(events)))
+}
+
+async fn handle_search(
+    search_request: SearchRequest,
+    brain: Arc<Brain>,
+) -> Result<impl Reply, Rejection> {
+    info!("get search request: {:?}", search_request.query);
+    let top_k = search_request.top_k.unwrap_or(5);
+    match brain
+        .search(&search_request.query, top_k, &search_request.filter)
+        .await
+    {
+        Ok(hits) => Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({ "hits": hits })),
+            StatusCode::OK,
+        )),
+        Err(e) => {
+            warn!("handle search request failed: {}", e);
+            Ok(warp::reply::with_status(
+                warp::reply::json(&serde_json::json!({ "error": e.to_string() })),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ))
+        }
+    }
+}
+
+// daily token and cost totals of the latest days, 30 by default
+async fn handle_usage(
+    usage_request: UsageRequest,
+    authorization: Option<String>,
+    brain: Arc<Brain>,
+) -> Result<impl Reply, Rejection> {
+    // records are named by the query text
+    if ADMIN_TOKEN.is_empty() || authorization != Some(format!("Bearer {}", *ADMIN_TOKEN)) {
+        warn!("reject unauthorized usage request");
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({ "error": "unauthorized" })),
+            StatusCode::UNAUTHORIZED,
+        ));
+    }
+    let days = usage_request.days.unwrap_or(30);
+    match brain.usage.report(days).await {
+        Ok(report) => Ok(warp::reply::with_status(
+            warp::reply::json(&report),
+            StatusCode::OK,
+        )),
+        Err(e) => {
+            warn!("handle usage request failed: {}", e);
+            Ok(warp::reply::with_status(
+                warp::reply::json(&serde_json::json!({ "error": e.to_string() })),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ))
+        }
+    }
+}
+
+async fn handle_view(
+    view_request: ViewRequest,
+    brain: Arc<Brain>,
+) -> Result<impl Reply, Rejection> {
+    info!(
+        "get view request: {}, page: {:?}, index: {:?}, chunk: {:?}",
+        view_request.file, view_request.page, view_request.index, view_request.chunk
+    );
+    let file_name = view_request.file;
+    let file_path = PathBuf::from("./files").join(file_name.clone());
+    // members of an archive are named like "archive.zip/path/inside"
+    let safe = !file_name.starts_with('/')
+        && !file_name.contains('\\')
+        && file_name
+            .split('/')
+            .all(|p| !p.is_empty() && p != "." && p != "..");
+    if !safe || !file_path.is_file() {
+        return Ok(warp::reply::with_status(
+            warp::reply::html("文件不存在".to_string()),
+            StatusCode::NOT_FOUND,
+        ));
+    }
+
+    let mut from = view_request.page.unwrap_or(1);
+    let mut to = from;
+    let mut highlight = None;
+    if let (Some(index), Some(chunk)) = (view_request.index, view_request.chunk) {
+        match brain.load_chunk(index, chunk).await {
+            Ok(loaded) if loaded.file_name == file_name => {
+                let chunk = &loaded.chunks[0];
+                if view_request.page.is_none() {
+                    from = chunk.page;
+                    to = chunk.end_page.max(chunk.page);
+                }
+                // chunks stored before offsets were recorded have none
+                if chunk.char_end > chunk.char_start {
+                    highlight = Some((chunk.char_start, chunk.char_end));
+                }
+            }
+            Ok(_) => {
+                return Ok(warp::reply::with_status(
+                    warp::reply::html("片段与文件不匹配".to_string()),
+                    StatusCode::BAD_REQUEST,
+                ))
+            }
+            Err(e) => {
+                warn!("view load chunk failed: {}", e);
+                return Ok(warp::reply::with_status(
+                    warp::reply::html("片段不存在".to_string()),
+                    StatusCode::NOT_FOUND,
+                ));
+            }
+        }
+    }
+
+    let file = match match_file(file_name.clone(), "".to_string(), file_path, None) {
+        Ok(file) => file,
+        Err(e) => {
+            warn!("view {} failed: {}", file_name, e);
+            return Ok(warp::reply::with_status(
+                warp::reply::html("不支持的文件类型".to_string()),
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ));
+        }
+    };
+    let unit_kind = file.parser.unit_kind();
+    match tokio::task::spawn_blocking(move || extract(&file)).await {
+        Ok(Ok(document)) => Ok(warp::reply::with_status(
+            warp::reply::html(viewer::render_view(
+                &file_name,
+                &document.units,
+                unit_kind,
+                from,
+                to,
+                highlight,
+            )),
+            StatusCode::OK,
+        )),
+        Ok(Err(e)) => {
+            warn!("view extract {} failed: {}", file_name, e);
+            Ok(warp::reply::with_status(
+                warp::reply::html("解析文件失败".to_string()),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ))
+        }
+        Err(e) => {
+            warn!("view extract {} panicked: {}", file_name, e);
+            Ok(warp::reply::with_status(
+                warp::reply::html("解析文件失败".to_string()),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ))
+        }
+    }
 }
 
 async fn handle_upload(
     form: FormData,
     file_sender: Sender<UnlearnedFile>,
+    brain: Arc<Brain>,
 ) -> Result<impl warp::Reply, Infallible> {
+    if let Err(e) = brain.usage.check_budget() {
+        warn!("reject upload request: {}", e);
+        return Ok(warp::reply::with_status(
+            warp::reply::html("本月预算已用完，暂不接受上传".to_string()),
+            StatusCode::FORBIDDEN,
+        ));
+    }
     let mut stream = form.into_stream();
 
     while let Ok(Some(part)) = stream.try_next().await {
@@ -171,41 +693,182 @@ async fn handle_upload(
             let file_path = PathBuf::from("./files").join(file_name.clone());
             if file_path.exists() {
                 info!("get upload request: {} already uploaded", file_name);
-                return Ok(warp::reply::html("文件已存在"));
+                return Ok(warp::reply::with_status(
+                    warp::reply::html("文件已存在".to_string()),
+                    StatusCode::CONFLICT,
+                ));
             }
-            // parse file type
-            let file = match_file(file_name.clone(), "".to_string(), file_path.clone());
-
+            let mime_type = part.content_type().map(|m| m.to_string());
             // write file
             let mut fs = fs::File::create(file_path.clone()).await.unwrap(); // should not panic
             let mut part_stream = part.stream();
             while let Ok(Some(chunk)) = part_stream.try_next().await {
                 if let Err(e) = fs.write_all(chunk.chunk()).await {
                     error!("write {} failed: {}", file_name, e);
-                    return Ok(warp::reply::html("写入文件失败"));
+                    return Ok(warp::reply::with_status(
+                        warp::reply::html("写入文件失败".to_string()),
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                    ));
                 }
             }
+            if let Err(e) = fs.flush().await {
+                error!("write {} failed: {}", file_name, e);
+                return Ok(warp::reply::with_status(
+                    warp::reply::html("写入文件失败".to_string()),
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                ));
+            }
+            drop(fs);
+
+            if archive::is_archive(&file_path) {
+                return Ok(upload_archive(file_name, file_path, &file_sender).await);
+            }
+
+            // parse file type, unsupported files are not kept
+            let file = match match_file(
+                file_name.clone(),
+                "".to_string(),
+                file_path.clone(),
+                mime_type.as_deref(),
+            ) {
+                Ok(file) => file,
+                Err(e) => {
+                    warn!("reject upload request: {}", e);
+                    let _ = fs::remove_file(&file_path).await;
+                    return Ok(warp::reply::with_status(
+                        warp::reply::html(format!("不支持的文件类型（{}）", e)),
+                        StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                    ));
+                }
+            };
             info!("get upload request: {} uploaded", file_name);
             // send to indexer
             let _ = file_sender.send(file).await;
         }
     }
-    Ok(warp::reply::html("文件上传成功，正在建立索引..."))
+    Ok(warp::reply::with_status(
+        warp::reply::html("文件上传成功，正在建立索引...".to_string()),
+        StatusCode::OK,
+    ))
 }
 
-async fn indexer(mut file_receiver: Receiver<UnlearnedFile>, brain: Arc<Brain>) {
+// the members are expanded next to the other uploads, each one reported on its own line
+async fn upload_archive(
+    file_name: String,
+    file_path: PathBuf,
+    file_sender: &Sender<UnlearnedFile>,
+) -> warp::reply::WithStatus<warp::reply::Html<String>> {
+    // the directory of the members takes the name of the archive
+    let archive_path = PathBuf::from("./files").join(format!(".{}.uploading", file_name));
+    if let Err(e) = fs::rename(&file_path, &archive_path).await {
+        error!("move {} failed: {}", file_name, e);
+        let _ = fs::remove_file(&file_path).await;
+        return warp::reply::with_status(
+            warp::reply::html("写入文件失败".to_string()),
+            StatusCode::INTERNAL_SERVER_ERROR,
+        );
+    }
+    let expanded = {
+        let (file_name, archive_path, file_path) =
+            (file_name.clone(), archive_path.clone(), file_path.clone());
+        tokio::task::spawn_blocking(move || {
+            archive::expand(&file_name, &archive_path, &file_path, "")
+        })
+        .await
+    };
+    let _ = fs::remove_file(&archive_path).await;
+    let members = match expanded {
+        Ok(Ok(members)) => members,
+        Ok(Err(e)) => {
+            warn!("reject upload request: {}: {}", file_name, e);
+            let _ = fs::remove_dir_all(&file_path).await;
+            return warp::reply::with_status(
+                warp::reply::html(format!("压缩包解压失败（{}）", e)),
+                StatusCode::BAD_REQUEST,
+            );
+        }
+        Err(e) => {
+            error!("expand {} failed: {}", file_name, e);
+            let _ = fs::remove_dir_all(&file_path).await;
+            return warp::reply::with_status(
+                warp::reply::html("压缩包解压失败".to_string()),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            );
+        }
+    };
+
+    let mut report = vec![];
+    let mut accepted = 0;
+    for member in members {
+        match member.file {
+            Ok(file) => {
+                info!("get upload request: {} uploaded", member.name);
+                report.push(format!("{}：上传成功，正在建立索引...", member.name));
+                accepted += 1;
+                let _ = file_sender.send(file).await;
+            }
+            Err(e) => {
+                warn!("skip archive member {}: {}", member.name, e);
+                report.push(format!("{}：失败（{}）", member.name, e));
+            }
+        }
+    }
+    if accepted == 0 {
+        let _ = fs::remove_dir_all(&file_path).await;
+        report.push("压缩包中没有支持的文件".to_string());
+        return warp::reply::with_status(
+            warp::reply::html(report.join("\n")),
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        );
+    }
+    warp::reply::with_status(warp::reply::html(report.join("\n")), StatusCode::OK)
+}
+
+async fn indexer(
+    mut file_receiver: Receiver<UnlearnedFile>,
+    file_sender: Sender<UnlearnedFile>,
+    brain: Arc<Brain>,
+) {
     info!("indexer start");
+    // failed attempts of each file, cleared once it is indexed
+    let mut failures: HashMap<String, usize> = HashMap::new();
     while let Some(file) = file_receiver.recv().await {
         info!("indexer recieve file: {}", file);
         match file.clone().into() {
             Ok(unlearned) => {
                 let file_name = unlearned.file_name.clone();
+                // re-queued and startup files too, the rest is indexed on the next startup
+                if let Err(e) = brain.usage.check_budget() {
+                    warn!("indexer skip {}: {}", file_name, e);
+                    failures.remove(&file_name);
+                    continue;
+                }
                 match brain.index(unlearned).await {
                     Ok(_) => {
+                        failures.remove(&file_name);
                         info!("indexer index {} succeed", file_name);
                     }
                     Err(e) => {
-                        warn!("indexer index {} failed: {}", file_name, e);
+                        let failed = failures.entry(file_name.clone()).or_insert(0);
+                        *failed += 1;
+                        if *failed > *INDEX_RETRIES {
+                            failures.remove(&file_name);
+                            warn!("indexer index {} failed, give up: {}", file_name, e);
+                            continue;
+                        }
+                        // re-queue later, sending from another task because the channel is bounded
+                        let delay = Duration::from_secs(30 << (*failed - 1));
+                        warn!(
+                            "indexer index {} failed: {}, re-queue in {}s",
+                            file_name,
+                            e,
+                            delay.as_secs()
+                        );
+                        let file_sender = file_sender.clone();
+                        tokio::spawn(async move {
+                            tokio::time::sleep(delay).await;
+                            let _ = file_sender.send(file).await;
+                        });
                     }
                 }
             }
@@ -216,12 +879,24 @@ async fn indexer(mut file_receiver: Receiver<UnlearnedFile>, brain: Arc<Brain>)
     }
 }
 
+// names relative to dir, members of expanded archives are "archive.zip/path/inside"
+// hidden files are skipped, e.g. an archive being expanded
 async fn read_file_names(dir: PathBuf) -> Result<Vec<String>> {
     let mut file_names = Vec::new();
-    let mut entries = fs::read_dir(dir).await?;
-    while let Some(entry) = entries.next_entry().await? {
-        if entry.file_type().await?.is_file() {
-            file_names.push(entry.file_name().to_string_lossy().to_string());
+    let mut dirs = vec![(dir, String::new())];
+    while let Some((dir, prefix)) = dirs.pop() {
+        let mut entries = fs::read_dir(dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name.starts_with('.') {
+                continue;
+            }
+            let file_type = entry.file_type().await?;
+            if file_type.is_file() {
+                file_names.push(prefix.clone() + &name);
+            } else if file_type.is_dir() {
+                dirs.push((entry.path(), prefix.clone() + &name + "/"));
+            }
         }
     }
     Ok(file_names)
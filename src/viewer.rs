@@ -0,0 +1,107 @@
+// extracted-text view of an uploaded file, pages from..=to, with the cited chunk highlighted
+//
+// units and offsets are the same as in chunk_file::chunk, so a chunk's char_start/char_end
+// can be applied to the units returned by chunk_file::extract directly
+
+use crate::chunk_file::{Unit, UnitKind};
+
+pub fn render_view(
+    file_name: &str,
+    units: &[Unit],
+    unit_kind: UnitKind,
+    from: usize,
+    to: usize,
+    highlight: Option<(usize, usize)>,
+) -> String {
+    let mut body = String::new();
+    let mut offset = 0;
+    let mut marked = false;
+    for (i, unit) in units.iter().enumerate() {
+        let page = i + 1;
+        let chars = unit.text.chars().collect::<Vec<_>>();
+        if page >= from && page <= to {
+            let title = match unit_kind {
+                UnitKind::Page => format!("第 {} 页", page),
+                UnitKind::Paragraph => format!("第 {} 段", page),
+                UnitKind::Sheet => format!("工作表 {}", unit.section),
+                UnitKind::Slide => format!("第 {} 张幻灯片", page),
+            };
+            body += &format!(
+                "<h3>{}</h3>\n<pre>",
+                escape(&title.chars().collect::<Vec<_>>())
+            );
+            // part of the highlight inside this unit, in local char indices
+            let local = highlight.and_then(|(start, end)| {
+                let local_start = start.max(offset) - offset;
+                let local_end = end.min(offset + chars.len()).saturating_sub(offset);
+                (local_start < local_end).then_some((local_start, local_end))
+            });
+            match local {
+                Some((local_start, local_end)) => {
+                    let id = if marked { "" } else { " id=\"highlight\"" };
+                    marked = true;
+                    body += &escape(&chars[..local_start]);
+                    body += &format!("<mark{}>", id);
+                    body += &escape(&chars[local_start..local_end]);
+                    body += "</mark>";
+                    body += &escape(&chars[local_end..]);
+                }
+                None => body += &escape(&chars),
+            }
+            body += "</pre>\n";
+        }
+        offset += chars.len() + 1;
+    }
+
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="UTF-8">
+<title>{name}</title>
+<style>
+body {{ font-family: 'Courier New', monospace; margin: 2em; }}
+pre {{ white-space: pre-wrap; background-color: #f0f0f0; padding: 10px; border-radius: 10px; }}
+mark {{ background-color: #ffe066; }}
+</style>
+</head>
+<body>
+<h2>{name}</h2>
+<a href="/files/{url}" download>下载原文件</a>
+{body}
+<script>
+const highlight = document.getElementById("highlight");
+if (highlight) {{ highlight.scrollIntoView({{ block: "center" }}); }}
+</script>
+</body>
+</html>"#,
+        name = escape(&file_name.chars().collect::<Vec<_>>()),
+        url = url_encode(file_name),
+        body = body
+    )
+}
+
+fn escape(chars: &[char]) -> String {
+    let mut escaped = String::new();
+    for c in chars {
+        match c {
+            '<' => escaped += "&lt;",
+            '>' => escaped += "&gt;",
+            '&' => escaped += "&amp;",
+            '"' => escaped += "&quot;",
+            _ => escaped.push(*c),
+        }
+    }
+    escaped
+}
+
+fn url_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}